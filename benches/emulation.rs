@@ -0,0 +1,173 @@
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+
+use rust_chip8::assembler::assemble;
+use rust_chip8::cpu::{Cpu, build_cpu};
+use rust_chip8::palette::builtin_palettes;
+use rust_chip8::phosphor::{DEFAULT_DECAY, Persistence, build_phosphor, render_display};
+use rust_chip8::quirks::build_quirks;
+
+const INSTRUCTIONS: u64 = 100_000;
+const SPRITES: u64 = 10_000;
+const CYCLES_PER_FRAME: u64 = 12;
+
+// Register arithmetic and skips with no memory traffic
+const ALU_SOURCE: &str = "
+: main
+  v1 := 3
+  loop
+    v0 += v1
+    v2 -= v1
+    v3 ^= v0
+    v4 |= v2
+    v5 &= v3
+    v6 >>= v6
+    if v0 == 0 then v7 += 1
+  again
+";
+
+// BCD stores and register save/load through I
+const MEMORY_SOURCE: &str = "
+: main
+  loop
+    i := buffer
+    v0 += 7
+    bcd v0
+    save v7
+    i := buffer
+    load v7
+  again
+: buffer
+  0 0 0 0 0 0 0 0
+";
+
+const CALL_SOURCE: &str = "
+: leaf
+  v1 += 1
+;
+: middle
+  leaf
+  leaf
+;
+: main
+  loop
+    middle
+    v0 += 1
+  again
+";
+
+// A full-height sprite moved across the screen so it clips and collides
+const DRAW_SOURCE: &str = "
+: main
+  i := block
+  loop
+    sprite v0 v1 15
+    v0 += 3
+    v1 += 1
+  again
+: block
+  0xFF 0x81 0xBD 0xA5 0xA5 0xBD 0x81 0xFF
+  0xFF 0x81 0xBD 0xA5 0xA5 0xBD 0x81
+";
+
+fn rom_cpu(name: &str) -> Cpu {
+    let mut cpu = build_cpu();
+    let path = format!("{}/roms/{}", env!("CARGO_MANIFEST_DIR"), name);
+    cpu.load_rom(&path).unwrap();
+    cpu
+}
+
+fn source_cpu(source: &str) -> Cpu {
+    let mut cpu = build_cpu();
+    cpu.load_program(&assemble(source).unwrap().bytes);
+    let mut quirks = build_quirks();
+    quirks.display_wait = false;
+    cpu.set_quirks(quirks);
+    cpu
+}
+
+// Frames end as soon as their cycles are used up or the display-wait quirk
+// holds the CPU, so every counted cycle executes an instruction
+fn run_instructions(cpu: &mut Cpu, count: u64) {
+    let mut executed = 0;
+    let mut frame_cycles = 0;
+    while executed < count {
+        if cpu.is_waiting_for_display() || frame_cycles == CYCLES_PER_FRAME {
+            cpu.end_frame();
+            cpu.decrement_timers();
+            frame_cycles = 0;
+            continue;
+        }
+        cpu.cycle();
+        executed += 1;
+        frame_cycles += 1;
+    }
+}
+
+fn cycle_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cycle");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    for rom in ["corax.ch8", "tetris.ch8"] {
+        group.bench_function(rom, |b| {
+            b.iter_batched(
+                || rom_cpu(rom),
+                |mut cpu| run_instructions(&mut cpu, INSTRUCTIONS),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    for (name, source) in [
+        ("alu", ALU_SOURCE),
+        ("memory", MEMORY_SOURCE),
+        ("calls", CALL_SOURCE),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || source_cpu(source),
+                |mut cpu| run_instructions(&mut cpu, INSTRUCTIONS),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// The draw loop runs three instructions per sprite
+fn draw_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw_sprite");
+    group.throughput(Throughput::Elements(SPRITES));
+    group.bench_function("8x15", |b| {
+        b.iter_batched(
+            || source_cpu(DRAW_SOURCE),
+            |mut cpu| run_instructions(&mut cpu, 1 + SPRITES * 3),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn render_benches(c: &mut Criterion) {
+    let mut cpu = rom_cpu("ibm.ch8");
+    run_instructions(&mut cpu, 1_000);
+    let display = cpu.get_display();
+    let palette = &builtin_palettes()[0];
+    let mut frame = vec![0; display.pixels.len() * 4];
+
+    let mut group = c.benchmark_group("render_display");
+    group.throughput(Throughput::Elements(1));
+    for (name, mode) in [
+        ("off", Persistence::Off),
+        ("decay", Persistence::Decay(DEFAULT_DECAY)),
+    ] {
+        let mut phosphor = build_phosphor(mode);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                phosphor.update(&display, Some(display.bounds()));
+                render_display(&phosphor, &mut frame, palette, None);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, cycle_benches, draw_benches, render_benches);
+criterion_main!(benches);
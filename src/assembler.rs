@@ -0,0 +1,600 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::instruction::{Instruction, encode};
+use crate::symbols::{build_symbols, save_symbols, symbols_location};
+
+pub const PROGRAM_START: u16 = 0x200;
+
+pub struct Program {
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<String, u16>,
+    pub lines: BTreeMap<u16, usize>,
+}
+
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+struct Fixup {
+    position: usize,
+    instruction: fn(u16) -> Instruction,
+    label: String,
+    line: usize,
+}
+
+enum Block {
+    Loop { start: u16, breaks: Vec<usize> },
+    If { jump: usize },
+    Else { jump: usize },
+}
+
+enum Condition {
+    Equals(u8, u8),
+    NotEquals(u8, u8),
+    EqualsRegister(u8, u8),
+    NotEqualsRegister(u8, u8),
+    Key(u8),
+    NotKey(u8),
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    bytes: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, u16>,
+    lines: BTreeMap<u16, usize>,
+    line: usize,
+    constants: HashMap<String, u16>,
+    aliases: HashMap<String, u8>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+// Assembles the core of the Octo language: labels, constants, aliases,
+// structured control flow and every CHIP-8 instruction. Macros, `:calc`,
+// `:stringmode` and XO-CHIP extensions are rejected.
+pub fn assemble(source: &str) -> Result<Program, String> {
+    let tokens = tokenize(source);
+    let mut assembler = Assembler {
+        tokens,
+        pos: 0,
+        bytes: Vec::new(),
+        here: 0,
+        labels: HashMap::new(),
+        lines: BTreeMap::new(),
+        line: 0,
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    assembler.run()?;
+    Ok(Program {
+        bytes: assembler.bytes,
+        labels: assembler.labels,
+        lines: assembler.lines,
+    })
+}
+
+// Assembles a source file into a ROM and writes its symbols next to it.
+// Returns the process exit code.
+pub fn assemble_command(args: &[String]) -> i32 {
+    let [source_path, rom_path] = args else {
+        eprintln!("Usage: rust-chip8 assemble <SOURCE> <ROM>");
+        return 2;
+    };
+
+    let result = std::fs::read_to_string(source_path)
+        .map_err(|e| format!("Failed to read source file at '{}': {}", source_path, e))
+        .and_then(|source| {
+            assemble(&source).map_err(|e| format!("Failed to assemble '{}': {}", source_path, e))
+        })
+        .and_then(|program| {
+            std::fs::write(rom_path, &program.bytes)
+                .map_err(|e| format!("Failed to write ROM '{}': {}", rom_path, e))?;
+            let source = std::fs::canonicalize(source_path).map_or_else(
+                |_| source_path.clone(),
+                |p| p.to_string_lossy().into_owned(),
+            );
+            let location = symbols_location(rom_path);
+            save_symbols(&build_symbols(&program, Some(&source)), &location)?;
+            println!(
+                "Wrote {} bytes to '{}' and symbols to '{}'",
+                program.bytes.len(),
+                rom_path,
+                location
+            );
+            Ok(())
+        });
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for text in code.split_whitespace() {
+            tokens.push(Token {
+                text,
+                line: index + 1,
+            });
+        }
+    }
+    tokens
+}
+
+pub fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse::<i32>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+impl<'a> Assembler<'a> {
+    fn run(&mut self) -> Result<(), String> {
+        let main_first = matches!(
+            (self.tokens.first(), self.tokens.get(1)),
+            (Some(colon), Some(name)) if colon.text == ":" && name.text == "main"
+        );
+        let has_main = self
+            .tokens
+            .windows(2)
+            .any(|pair| pair[0].text == ":" && pair[1].text == "main");
+        if has_main && !main_first {
+            self.emit_address(Instruction::Jp, "main", 1);
+        }
+
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let name = match block {
+                Block::Loop { .. } => "loop",
+                Block::If { .. } | Block::Else { .. } => "begin",
+            };
+            return Err(format!("Unterminated '{}' at end of file", name));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = *self
+                .labels
+                .get(&fixup.label)
+                .ok_or_else(|| format!("line {}: undefined label '{}'", fixup.line, fixup.label))?;
+            self.patch(fixup.position, (fixup.instruction)(address));
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<&Token<'a>, String> {
+        let line = self.tokens.last().map_or(0, |t| t.line);
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| format!("line {}: unexpected end of file", line))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.text)
+    }
+
+    fn address(&self) -> u16 {
+        PROGRAM_START + self.here as u16
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        if self.here >= self.bytes.len() {
+            self.bytes.resize(self.here + 1, 0);
+        }
+        self.bytes[self.here] = byte;
+        if self.line > 0 {
+            self.lines.insert(self.address(), self.line);
+        }
+        self.here += 1;
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        let opcode = encode(instruction);
+        self.emit_byte((opcode >> 8) as u8);
+        self.emit_byte(opcode as u8);
+    }
+
+    fn patch(&mut self, position: usize, instruction: Instruction) {
+        let opcode = encode(instruction);
+        self.bytes[position] = (opcode >> 8) as u8;
+        self.bytes[position + 1] = opcode as u8;
+    }
+
+    // Forward references are emitted with address 0 and patched at the end
+    fn emit_address(&mut self, instruction: fn(u16) -> Instruction, label: &str, line: usize) {
+        match self.labels.get(label) {
+            Some(&address) => self.emit(instruction(address)),
+            None => {
+                self.fixups.push(Fixup {
+                    position: self.here,
+                    instruction,
+                    label: label.to_string(),
+                    line,
+                });
+                self.emit(instruction(0));
+            }
+        }
+    }
+
+    fn error(&self, line: usize, message: &str) -> String {
+        format!("line {}: {}", line, message)
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        let (text, line) = (token.text, token.line);
+        parse_register(text)
+            .or_else(|| self.aliases.get(text).copied())
+            .ok_or_else(|| self.error(line, &format!("expected a register, found '{}'", text)))
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        parse_register(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    fn value(&mut self) -> Result<i32, String> {
+        let token = self.next()?;
+        let (text, line) = (token.text, token.line);
+        parse_number(text)
+            .or_else(|| self.constants.get(text).map(|&v| v as i32))
+            .ok_or_else(|| self.error(line, &format!("expected a number, found '{}'", text)))
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let line = self.tokens.get(self.pos).map_or(0, |t| t.line);
+        let value = self.value()?;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(line, &format!("value {} does not fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        let line = self.tokens.get(self.pos).map_or(0, |t| t.line);
+        let value = self.value()?;
+        if !(0..=15).contains(&value) {
+            return Err(self.error(line, &format!("value {} does not fit in a nibble", value)));
+        }
+        Ok(value as u16)
+    }
+
+    fn address_operand(&mut self, instruction: fn(u16) -> Instruction) -> Result<(), String> {
+        let token = self.next()?;
+        let (text, line) = (token.text, token.line);
+        if let Some(value) =
+            parse_number(text).or_else(|| self.constants.get(text).map(|&v| v as i32))
+        {
+            if !(0..=0xFFF).contains(&value) {
+                return Err(self.error(line, &format!("address {} is out of range", value)));
+            }
+            self.emit(instruction(value as u16));
+        } else {
+            self.emit_address(instruction, text, line);
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        let (text, line) = (token.text, token.line);
+        if text != expected {
+            let message = format!("expected '{}', found '{}'", expected, text);
+            return Err(self.error(line, &message));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        let (text, line) = (token.text, token.line);
+        self.line = line;
+
+        match text {
+            ":" => {
+                let name = self.next()?.text.to_string();
+                if self.labels.insert(name.clone(), self.address()).is_some() {
+                    return Err(self.error(line, &format!("label '{}' defined twice", name)));
+                }
+            }
+            ":const" => {
+                let name = self.next()?.text.to_string();
+                let value = self.value()?;
+                self.constants.insert(name, value as u16);
+            }
+            ":alias" => {
+                let name = self.next()?.text.to_string();
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":org" => {
+                let value = self.value()?;
+                if !(PROGRAM_START as i32..=0xFFF).contains(&value) {
+                    return Err(self.error(line, &format!("cannot :org to {:#x}", value)));
+                }
+                self.here = (value as u16 - PROGRAM_START) as usize;
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(byte);
+            }
+            ":call" => self.address_operand(Instruction::Call)?,
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.emit(Instruction::Cls),
+            "return" | ";" => self.emit(Instruction::Ret),
+            "jump" => self.address_operand(Instruction::Jp)?,
+            "jump0" => self.address_operand(Instruction::JpV0)?,
+            "native" => self.address_operand(Instruction::Sys)?,
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()? as u8;
+                self.emit(Instruction::Drw { x, y, n });
+            }
+            "bcd" => self.emit_register(Instruction::LdB)?,
+            "save" => self.emit_register(Instruction::LdIVx)?,
+            "load" => self.emit_register(Instruction::LdVxI)?,
+            "delay" => {
+                self.expect(":=")?;
+                self.emit_register(Instruction::LdDtVx)?;
+            }
+            "buzzer" => {
+                self.expect(":=")?;
+                self.emit_register(Instruction::LdStVx)?;
+            }
+            "i" => self.i_statement()?,
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.address(),
+                breaks: Vec::new(),
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                self.emit(skip_when(&condition));
+                let position = self.here;
+                self.emit(Instruction::Jp(0));
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|b| matches!(b, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(position),
+                    _ => return Err(self.error(line, "'while' outside of a loop")),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks }) => {
+                    self.emit(Instruction::Jp(start));
+                    let end = self.address();
+                    for position in breaks {
+                        self.patch(position, Instruction::Jp(end));
+                    }
+                }
+                _ => return Err(self.error(line, "'again' without a matching 'loop'")),
+            },
+            "if" => {
+                let condition = self.condition()?;
+                let token = self.next()?;
+                let (keyword, keyword_line) = (token.text, token.line);
+                match keyword {
+                    "then" => self.emit(skip_unless(&condition)),
+                    "begin" => {
+                        self.emit(skip_when(&condition));
+                        let jump = self.here;
+                        self.emit(Instruction::Jp(0));
+                        self.blocks.push(Block::If { jump });
+                    }
+                    other => {
+                        let message = format!("expected 'then' or 'begin', found '{}'", other);
+                        return Err(self.error(keyword_line, &message));
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump }) => {
+                    let else_jump = self.here;
+                    self.emit(Instruction::Jp(0));
+                    self.patch(jump, Instruction::Jp(self.address()));
+                    self.blocks.push(Block::Else { jump: else_jump });
+                }
+                _ => return Err(self.error(line, "'else' without a matching 'begin'")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump }) | Some(Block::Else { jump }) => {
+                    self.patch(jump, Instruction::Jp(self.address()));
+                }
+                _ => return Err(self.error(line, "'end' without a matching 'begin'")),
+            },
+            ":macro" | ":calc" | ":stringmode" | ":unpack" | ":next" | ":assert" => {
+                return Err(self.error(line, &format!("'{}' is not supported", text)));
+            }
+            _ if self.is_register(text) => {
+                self.pos -= 1;
+                self.register_statement()?;
+            }
+            _ => {
+                if let Some(value) = parse_number(text) {
+                    if !(-128..=255).contains(&value) {
+                        let message = format!("value {} does not fit in a byte", value);
+                        return Err(self.error(line, &message));
+                    }
+                    self.emit_byte(value as u8);
+                } else if let Some(&value) = self.constants.get(text) {
+                    self.emit_byte(value as u8);
+                } else {
+                    self.emit_address(Instruction::Call, text, line);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_register(&mut self, instruction: fn(u8) -> Instruction) -> Result<(), String> {
+        let x = self.register()?;
+        self.emit(instruction(x));
+        Ok(())
+    }
+
+    fn i_statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        let (op, line) = (token.text, token.line);
+        match op {
+            ":=" => {
+                if self.peek() == Some("hex") {
+                    self.next()?;
+                    self.emit_register(Instruction::LdF)
+                } else {
+                    self.address_operand(Instruction::LdI)
+                }
+            }
+            "+=" => self.emit_register(Instruction::AddI),
+            _ => Err(self.error(line, &format!("unknown operator 'i {}'", op))),
+        }
+    }
+
+    fn register_statement(&mut self) -> Result<(), String> {
+        let x = self.register()?;
+        let token = self.next()?;
+        let (op, line) = (token.text, token.line);
+        let rhs = self.peek().unwrap_or("");
+        let rhs_is_register = self.is_register(rhs);
+
+        let instruction = match (op, rhs) {
+            (":=", "key") => {
+                self.next()?;
+                Instruction::LdVxK(x)
+            }
+            (":=", "delay") => {
+                self.next()?;
+                Instruction::LdVxDt(x)
+            }
+            (":=", "random") => {
+                self.next()?;
+                Instruction::Rnd {
+                    x,
+                    nn: self.byte()?,
+                }
+            }
+            _ if rhs_is_register => {
+                let y = self.register()?;
+                match op {
+                    ":=" => Instruction::LdReg { x, y },
+                    "|=" => Instruction::Or { x, y },
+                    "&=" => Instruction::And { x, y },
+                    "^=" => Instruction::Xor { x, y },
+                    "+=" => Instruction::AddReg { x, y },
+                    "-=" => Instruction::Sub { x, y },
+                    ">>=" => Instruction::Shr { x, y },
+                    "=-" => Instruction::Subn { x, y },
+                    "<<=" => Instruction::Shl { x, y },
+                    _ => return Err(self.error(line, &format!("unknown operator '{}'", op))),
+                }
+            }
+            (":=", _) => Instruction::LdByte {
+                x,
+                nn: self.byte()?,
+            },
+            ("+=", _) => Instruction::AddByte {
+                x,
+                nn: self.byte()?,
+            },
+            ("-=", _) => Instruction::AddByte {
+                x,
+                nn: self.byte()?.wrapping_neg(),
+            },
+            _ => return Err(self.error(line, &format!("unknown operator '{}'", op))),
+        };
+        self.emit(instruction);
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.register()?;
+        let token = self.next()?;
+        let (op, line) = (token.text, token.line);
+        match op {
+            "key" => Ok(Condition::Key(x)),
+            "-key" => Ok(Condition::NotKey(x)),
+            "==" | "!=" => {
+                let rhs = self.peek().unwrap_or("");
+                if self.is_register(rhs) {
+                    let y = self.register()?;
+                    Ok(if op == "==" {
+                        Condition::EqualsRegister(x, y)
+                    } else {
+                        Condition::NotEqualsRegister(x, y)
+                    })
+                } else {
+                    let value = self.byte()?;
+                    Ok(if op == "==" {
+                        Condition::Equals(x, value)
+                    } else {
+                        Condition::NotEquals(x, value)
+                    })
+                }
+            }
+            _ => Err(self.error(line, &format!("unsupported comparison '{}'", op))),
+        }
+    }
+}
+
+// Instruction that skips the next one when the condition holds
+fn skip_when(condition: &Condition) -> Instruction {
+    match *condition {
+        Condition::Equals(x, nn) => Instruction::SeByte { x, nn },
+        Condition::NotEquals(x, nn) => Instruction::SneByte { x, nn },
+        Condition::EqualsRegister(x, y) => Instruction::SeReg { x, y },
+        Condition::NotEqualsRegister(x, y) => Instruction::SneReg { x, y },
+        Condition::Key(x) => Instruction::Skp(x),
+        Condition::NotKey(x) => Instruction::Sknp(x),
+    }
+}
+
+// Instruction that skips the next one when the condition does not hold
+fn skip_unless(condition: &Condition) -> Instruction {
+    match *condition {
+        Condition::Equals(x, n) => skip_when(&Condition::NotEquals(x, n)),
+        Condition::NotEquals(x, n) => skip_when(&Condition::Equals(x, n)),
+        Condition::EqualsRegister(x, y) => skip_when(&Condition::NotEqualsRegister(x, y)),
+        Condition::NotEqualsRegister(x, y) => skip_when(&Condition::EqualsRegister(x, y)),
+        Condition::Key(x) => skip_when(&Condition::NotKey(x)),
+        Condition::NotKey(x) => skip_when(&Condition::Key(x)),
+    }
+}
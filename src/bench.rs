@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+use crate::cpu::{Cpu, build_cpu};
+use crate::loader::load_program;
+
+const DEFAULT_INSTRUCTIONS: u64 = 10_000_000;
+const CYCLES_PER_FRAME: usize = 12;
+
+// Runs a ROM uncapped with no cache, the decoded-instruction cache and the
+// block cache, reporting instructions per second for each. Returns the
+// process exit code.
+pub fn bench_command(args: &[String]) -> i32 {
+    let usage = || {
+        eprintln!("Usage: rust-chip8 bench <ROM> [INSTRUCTIONS]");
+        2
+    };
+    let (rom, instructions) = match args {
+        [rom] => (rom, DEFAULT_INSTRUCTIONS),
+        [rom, count] => match count.parse() {
+            Ok(count) if count > 0 => (rom, count),
+            _ => return usage(),
+        },
+        _ => return usage(),
+    };
+
+    let mut results = Vec::new();
+    let modes = [
+        ("decode every cycle", false, false),
+        ("decoded cache", true, false),
+        ("cached blocks", true, true),
+    ];
+    for (name, cached, blocks) in modes {
+        let mut cpu = build_cpu();
+        let loaded = match load_program(&mut cpu, rom) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        if let Some(options) = loaded.options {
+            cpu.set_quirks(options.quirks);
+        }
+        cpu.set_decode_cache(cached);
+        cpu.set_block_cache(blocks);
+
+        let elapsed = run(&mut cpu, instructions);
+        let rate = instructions as f64 / elapsed.as_secs_f64();
+        println!(
+            "{:<20} {:>8.3}s  {:>14.0} instructions/s",
+            name,
+            elapsed.as_secs_f64(),
+            rate
+        );
+        results.push(rate);
+    }
+    if let [before, cached, blocks] = results[..] {
+        println!(
+            "Speedup: {:.2}x cached, {:.2}x blocks",
+            cached / before,
+            blocks / before
+        );
+    }
+    0
+}
+
+// Frames end as soon as their cycles are used up, without waiting for the
+// next 60 Hz tick
+fn run(cpu: &mut Cpu, instructions: u64) -> Duration {
+    let start = Instant::now();
+    let mut executed = 0;
+    while executed < instructions {
+        executed += cpu.run(CYCLES_PER_FRAME) as u64;
+        cpu.end_frame();
+        cpu.decrement_timers();
+    }
+    start.elapsed()
+}
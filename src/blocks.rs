@@ -0,0 +1,254 @@
+use crate::instruction::{Instruction, decode_for};
+use crate::platform::Platform;
+
+pub const MAX_BLOCK_LENGTH: usize = 64;
+
+// The decoded instructions of a straight-line run starting at one address,
+// with invalid opcodes kept as errors. Blocks are cached per address and run
+// through the CPU's usual instruction match. Every instruction but the last
+// is straight-line code, so a block can run back to back without checking
+// pc, the display wait or faults until it ends.
+pub struct Block {
+    pub instructions: Vec<Result<Instruction, u16>>,
+}
+
+pub fn decode_block(memory: &[u8], start: u16, platform: Platform) -> Block {
+    let mut instructions = Vec::new();
+    let mut address = start as usize;
+    while address + 1 < memory.len() && instructions.len() < MAX_BLOCK_LENGTH {
+        let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+        let instruction = decode_for(platform, opcode).map_err(|_| opcode);
+        instructions.push(instruction);
+        if !instruction.is_ok_and(straight_line) {
+            break;
+        }
+        address += 2;
+    }
+    Block { instructions }
+}
+
+// Whether an instruction always moves on to the next word and can never
+// wait, fault or write memory. Anything else ends the block it is in, and
+// the match has no catch-all so new instructions have to be sorted here.
+fn straight_line(instruction: Instruction) -> bool {
+    match instruction {
+        Instruction::Cls
+        | Instruction::LdByte { .. }
+        | Instruction::AddByte { .. }
+        | Instruction::LdReg { .. }
+        | Instruction::Or { .. }
+        | Instruction::And { .. }
+        | Instruction::Xor { .. }
+        | Instruction::AddReg { .. }
+        | Instruction::Sub { .. }
+        | Instruction::Shr { .. }
+        | Instruction::Subn { .. }
+        | Instruction::Shl { .. }
+        | Instruction::LdI(_)
+        | Instruction::Rnd { .. }
+        | Instruction::LdVxDt(_)
+        | Instruction::LdDtVx(_)
+        | Instruction::LdStVx(_)
+        | Instruction::AddI(_)
+        | Instruction::LdF(_)
+        | Instruction::LdVxI(_)
+        | Instruction::Bgc
+        | Instruction::AddNib { .. }
+        | Instruction::Col { .. }
+        | Instruction::Out(_)
+        | Instruction::MegaOff
+        | Instruction::MegaOn
+        | Instruction::ScrollUp(_)
+        | Instruction::LdPal(_)
+        | Instruction::SprW(_)
+        | Instruction::SprH(_)
+        | Instruction::Alpha(_)
+        | Instruction::DigiSnd(_)
+        | Instruction::StopSnd
+        | Instruction::CCol(_)
+        | Instruction::Low
+        | Instruction::High
+        | Instruction::LdHf(_)
+        | Instruction::LdRVx(_)
+        | Instruction::LdVxR(_) => true,
+        // Jumps, skips and calls move pc elsewhere
+        Instruction::Sys(_)
+        | Instruction::Ret
+        | Instruction::Jp(_)
+        | Instruction::Call(_)
+        | Instruction::JpV0(_)
+        | Instruction::SeByte { .. }
+        | Instruction::SneByte { .. }
+        | Instruction::SeReg { .. }
+        | Instruction::SneReg { .. }
+        | Instruction::Skp(_)
+        | Instruction::Sknp(_)
+        | Instruction::Skp2(_)
+        | Instruction::Sknp2(_)
+        | Instruction::LdHi(_)
+        | Instruction::Exit => false,
+        // These can stop for a key, the input port or the display
+        Instruction::LdVxK(_)
+        | Instruction::In(_)
+        | Instruction::Drw { .. }
+        | Instruction::ScrollDown(_)
+        | Instruction::ScrollRight
+        | Instruction::ScrollLeft => false,
+        // These write memory or can fault
+        Instruction::LdB(_) | Instruction::LdIVx(_) | Instruction::BMode(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+    use crate::cpu::{Cpu, build_cpu};
+    use crate::platform::Platform;
+
+    const FRAMES: usize = 300;
+    const CYCLES_PER_FRAME: usize = 12;
+
+    // Register arithmetic, BCD and save/load through I, and nested calls
+    const ALU_SOURCE: &str = "
+: leaf
+  v1 += 1
+  v3 ^= v1
+;
+: main
+  loop
+    v0 += 7
+    v2 -= v0
+    v4 |= v2
+    v5 &= v3
+    v6 >>= v0
+    if v0 == 0 then v7 += 1
+    leaf
+    i := buffer
+    bcd v0
+    save v7
+    i := buffer
+    load v3
+  again
+: buffer
+  0 0 0 0 0 0 0 0
+";
+
+    // A sprite moved across the screen so it clips and collides
+    const DRAW_SOURCE: &str = "
+: main
+  i := block
+  loop
+    sprite v0 v1 8
+    v0 += 3
+    v1 += 1
+    v2 += vf
+  again
+: block
+  0xFF 0x81 0xBD 0xA5 0xA5 0xBD 0x81 0xFF
+";
+
+    // SCHIP scrolls in the middle of straight-line code, which the display
+    // wait has to stop at
+    const SCROLL_SOURCE: &str = "
+: main
+  i := block
+  loop
+    sprite v0 v1 8
+    0x00 0xC2
+    v0 += 5
+    0x00 0xFB
+    v1 += 1
+    0x00 0xFC
+    v2 += 1
+  again
+: block
+  0xFF 0x81 0xBD 0xA5 0xA5 0xBD 0x81 0xFF
+";
+
+    fn rom_cpu(name: &str) -> Cpu {
+        let mut cpu = build_cpu();
+        let path = format!("{}/roms/{}", env!("CARGO_MANIFEST_DIR"), name);
+        cpu.load_rom(&path).unwrap();
+        cpu
+    }
+
+    fn source_cpu(platform: Platform, source: &str, display_wait: bool) -> Cpu {
+        let mut cpu = build_cpu();
+        cpu.set_platform(platform);
+        cpu.load_font_data();
+        cpu.load_program(&assemble(source).unwrap().bytes);
+        let mut quirks = platform.quirks();
+        quirks.display_wait = display_wait;
+        cpu.set_quirks(quirks);
+        cpu
+    }
+
+    // Runs the same program with and without the block cache and checks the
+    // machine state matches after every frame
+    fn assert_blocks_match(name: &str, build: impl Fn() -> Cpu) {
+        let mut interpreted = build();
+        let mut blocks = build();
+        blocks.set_block_cache(true);
+        for frame in 0..FRAMES {
+            for cpu in [&mut interpreted, &mut blocks] {
+                cpu.run(CYCLES_PER_FRAME);
+                cpu.end_frame();
+                cpu.decrement_timers();
+            }
+            let (a, b) = (interpreted.registers(), blocks.registers());
+            let context = format!("{} differs after frame {}", name, frame);
+            assert_eq!(
+                (a.v, a.i, a.pc, a.sp),
+                (b.v, b.i, b.pc, b.sp),
+                "{}",
+                context
+            );
+            assert_eq!(a.delay_timer, b.delay_timer, "{}", context);
+            assert_eq!(a.sound_timer, b.sound_timer, "{}", context);
+            assert_eq!(interpreted.fault(), blocks.fault(), "{}", context);
+            assert!(interpreted.memory() == blocks.memory(), "{}", context);
+            assert!(
+                interpreted.get_display() == blocks.get_display(),
+                "{}",
+                context
+            );
+        }
+    }
+
+    #[test]
+    fn cached_blocks_match_the_interpreter_on_roms() {
+        for name in ["ibm.ch8", "corax.ch8", "flags.ch8", "quirks.ch8"] {
+            assert_blocks_match(name, || rom_cpu(name));
+        }
+    }
+
+    #[test]
+    fn cached_blocks_match_the_interpreter_on_sources() {
+        let sources = [
+            ("alu", Platform::Chip8, ALU_SOURCE),
+            ("draw", Platform::Chip8, DRAW_SOURCE),
+            ("scroll", Platform::Schip11, SCROLL_SOURCE),
+        ];
+        for (name, platform, source) in sources {
+            for display_wait in [false, true] {
+                let name = format!("{} with display wait {}", name, display_wait);
+                assert_blocks_match(&name, || source_cpu(platform, source, display_wait));
+            }
+        }
+    }
+
+    #[test]
+    fn blocks_stop_after_anything_that_is_not_straight_line() {
+        let program = assemble(SCROLL_SOURCE).unwrap().bytes;
+        let mut memory = vec![0; 0x1000];
+        memory[0x200..0x200 + program.len()].copy_from_slice(&program);
+        // i := block, then the sprite ends the first block
+        let block = super::decode_block(&memory, 0x200, Platform::Schip11);
+        assert_eq!(block.instructions.len(), 2);
+        // The scroll down ends the next one
+        let block = super::decode_block(&memory, 0x204, Platform::Schip11);
+        assert_eq!(block.instructions.len(), 1);
+        let block = super::decode_block(&memory, 0x206, Platform::Schip11);
+        assert_eq!(block.instructions.len(), 2);
+    }
+}
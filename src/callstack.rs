@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+
+const DEEP_STACK: usize = 12;
+
+// One active subroutine call. The entry is unknown for frames that appeared
+// without a CALL, e.g. when a debugger raised the stack pointer.
+#[derive(Clone, Copy)]
+pub struct Frame {
+    pub call_site: u16,
+    pub entry: Option<u16>,
+    pub return_address: u16,
+}
+
+// Shadow of the CPU stack that remembers which CALL pushed each return
+// address, and warns about ROMs that leave subroutines without returning.
+pub struct CallStack {
+    frames: Vec<Frame>,
+    warned: HashSet<u16>,
+    warnings: Vec<String>,
+}
+
+pub fn build_call_stack() -> CallStack {
+    CallStack {
+        frames: Vec::new(),
+        warned: HashSet::new(),
+        warnings: Vec::new(),
+    }
+}
+
+impl CallStack {
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn call(&mut self, call_site: u16, entry: u16) {
+        if self.frames.iter().any(|frame| frame.call_site == call_site) {
+            self.warn(
+                call_site,
+                format!(
+                    "CALL {:03X} at {:03X} was made again before the first call returned; \
+                     the subroutine may be exiting with a jump instead of RET",
+                    entry, call_site
+                ),
+            );
+        } else if self.frames.len() + 1 == DEEP_STACK {
+            self.warn(
+                call_site,
+                format!(
+                    "Call stack is {} frames deep at {:03X}",
+                    DEEP_STACK, call_site
+                ),
+            );
+        }
+        self.frames.push(Frame {
+            call_site,
+            entry: Some(entry),
+            return_address: call_site + 2,
+        });
+    }
+
+    pub fn ret(&mut self) {
+        self.frames.pop();
+    }
+
+    // Brings the frames back in line with the raw stack after something other
+    // than CALL/RET changed it
+    pub fn sync(&mut self, stack: &[u16]) {
+        self.frames.truncate(stack.len());
+        for (depth, &return_address) in stack.iter().enumerate() {
+            match self.frames.get(depth) {
+                Some(frame) if frame.return_address == return_address => {}
+                _ => {
+                    self.frames.truncate(depth);
+                    self.frames.push(Frame {
+                        call_site: return_address.wrapping_sub(2),
+                        entry: None,
+                        return_address,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn warn(&mut self, call_site: u16, message: String) {
+        if self.warned.insert(call_site) {
+            self.warnings.push(message);
+        }
+    }
+}
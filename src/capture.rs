@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::scaling::{ScaleMode, blit};
+
+const FRAME_RATE: usize = 60;
+
+pub enum Recorder {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        frame_index: usize,
+        size: (usize, usize),
+        scale: usize,
+    },
+    Raw {
+        writer: BufWriter<File>,
+        location: String,
+        size: (usize, usize),
+        scale: usize,
+    },
+}
+
+fn scale_frame(frame: &[u8], size: (usize, usize), scale: usize) -> Vec<u8> {
+    let scaled_size = (size.0 * scale, size.1 * scale);
+    let mut scaled = vec![0; scaled_size.0 * scaled_size.1 * 4];
+    blit(
+        ScaleMode::Integer,
+        frame,
+        size,
+        &mut scaled,
+        scaled_size,
+        [0, 0, 0, 255],
+    );
+    scaled
+}
+
+pub fn save_png(
+    location: &str,
+    frame: &[u8],
+    size: (usize, usize),
+    scale: usize,
+) -> Result<(), String> {
+    let file = File::create(location)
+        .map_err(|e| format!("Failed to create screenshot '{}': {}", location, e))?;
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        (size.0 * scale) as u32,
+        (size.1 * scale) as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&scale_frame(frame, size, scale)))
+        .map_err(|e| format!("Failed to write screenshot '{}': {}", location, e))
+}
+
+// `.gif` paths are recorded as an animated GIF; anything else receives a raw
+// RGBA stream that can be handed to ffmpeg.
+pub fn start_recording(
+    location: &str,
+    size: (usize, usize),
+    scale: usize,
+) -> Result<Recorder, String> {
+    let file = File::create(location)
+        .map_err(|e| format!("Failed to create recording '{}': {}", location, e))?;
+    let writer = BufWriter::new(file);
+
+    if !location.ends_with(".gif") {
+        return Ok(Recorder::Raw {
+            writer,
+            location: location.to_string(),
+            size,
+            scale,
+        });
+    }
+
+    let mut encoder = gif::Encoder::new(
+        writer,
+        (size.0 * scale) as u16,
+        (size.1 * scale) as u16,
+        &[],
+    )
+    .map_err(|e| format!("Failed to start recording '{}': {}", location, e))?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(|e| format!("Failed to start recording '{}': {}", location, e))?;
+
+    Ok(Recorder::Gif {
+        encoder,
+        frame_index: 0,
+        size,
+        scale,
+    })
+}
+
+impl Recorder {
+    pub fn add_frame(&mut self, frame: &[u8]) -> Result<(), String> {
+        match self {
+            Recorder::Gif {
+                encoder,
+                frame_index,
+                size,
+                scale,
+            } => {
+                let mut rgba = scale_frame(frame, *size, *scale);
+                let (width, height) = ((size.0 * *scale) as u16, (size.1 * *scale) as u16);
+                let mut gif_frame = indexed_frame(width, height, &rgba)
+                    .unwrap_or_else(|| gif::Frame::from_rgba_speed(width, height, &mut rgba, 10));
+
+                // GIF delays are in hundredths of a second, so spread the
+                // rounding across frames to average out at 60 FPS
+                let elapsed = |frames: usize| (frames * 100 + FRAME_RATE / 2) / FRAME_RATE;
+                gif_frame.delay = (elapsed(*frame_index + 1) - elapsed(*frame_index)) as u16;
+                *frame_index += 1;
+
+                encoder
+                    .write_frame(&gif_frame)
+                    .map_err(|e| format!("Failed to write GIF frame: {}", e))
+            }
+            Recorder::Raw {
+                writer,
+                size,
+                scale,
+                ..
+            } => writer
+                .write_all(&scale_frame(frame, *size, *scale))
+                .map_err(|e| format!("Failed to write frame: {}", e)),
+        }
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        match self {
+            Recorder::Gif { encoder, .. } => {
+                let mut writer = encoder
+                    .into_inner()
+                    .map_err(|e| format!("Failed to finish GIF: {}", e))?;
+                writer
+                    .flush()
+                    .map_err(|e| format!("Failed to finish GIF: {}", e))
+            }
+            Recorder::Raw {
+                mut writer,
+                location,
+                size,
+                scale,
+            } => {
+                writer
+                    .flush()
+                    .map_err(|e| format!("Failed to finish recording: {}", e))?;
+                println!(
+                    "Encode with: ffmpeg -f rawvideo -pixel_format rgba -video_size {}x{} -framerate {} -i {} output.mp4",
+                    size.0 * scale,
+                    size.1 * scale,
+                    FRAME_RATE,
+                    location
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+// Exact palette for frames with at most 256 colors, which is every frame
+// unless phosphor blending is enabled
+fn indexed_frame(width: u16, height: u16, rgba: &[u8]) -> Option<gif::Frame<'static>> {
+    let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(rgba.len() / 4);
+
+    for pixel in rgba.chunks_exact(4) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = match colors.get(&color) {
+            Some(&index) => index,
+            None => {
+                if colors.len() == 256 {
+                    return None;
+                }
+                let index = colors.len() as u8;
+                colors.insert(color, index);
+                palette.extend_from_slice(&color);
+                index
+            }
+        };
+        indices.push(index);
+    }
+
+    Some(gif::Frame::from_palette_pixels(
+        width, height, indices, palette, None,
+    ))
+}
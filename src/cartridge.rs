@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::Read;
+
+use serde_json::Value;
+
+use crate::assembler;
+use crate::palette::{Palette, build_palette, parse_color};
+use crate::quirks::{Quirks, build_quirks};
+
+pub struct Cartridge {
+    pub program: Vec<u8>,
+    pub options: Options,
+}
+
+pub struct Options {
+    pub tickrate: Option<usize>,
+    pub palette: Option<Palette>,
+    pub quirks: Quirks,
+}
+
+// Octo cartridges hide their payload in the low two bits of every pixel's
+// palette index, four pixels per byte, most significant bits first. The
+// payload is a big-endian length followed by a JSON document holding the
+// program source and the options it was written for.
+pub fn load_cartridge(location: &str) -> Result<Cartridge, String> {
+    let file = File::open(location)
+        .map_err(|e| format!("Failed to open cartridge '{}': {}", location, e))?;
+    read_cartridge(file, location)
+}
+
+fn read_cartridge(file: impl Read, location: &str) -> Result<Cartridge, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options
+        .read_info(file)
+        .map_err(|e| format!("Failed to decode cartridge '{}': {}", location, e))?;
+
+    let mut data = Vec::new();
+    let mut byte = 0u8;
+    let mut bits = 0;
+    while let Some(frame) = decoder
+        .read_next_frame()
+        .map_err(|e| format!("Failed to decode cartridge '{}': {}", location, e))?
+    {
+        for &pixel in frame.buffer.iter() {
+            byte = (byte << 2) | (pixel & 0x3);
+            bits += 2;
+            if bits == 8 {
+                data.push(byte);
+                byte = 0;
+                bits = 0;
+            }
+        }
+    }
+
+    if data.len() < 4 {
+        return Err(format!(
+            "'{}' does not contain a cartridge payload",
+            location
+        ));
+    }
+    let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let payload = data
+        .get(4..4 + size)
+        .ok_or_else(|| format!("'{}' has a truncated cartridge payload", location))?;
+
+    let json: Value = serde_json::from_slice(payload)
+        .map_err(|e| format!("Cartridge '{}' has invalid JSON: {}", location, e))?;
+
+    let source = json["program"]
+        .as_str()
+        .ok_or_else(|| format!("Cartridge '{}' has no program", location))?;
+    let program = assembler::assemble(source)
+        .map_err(|e| format!("Failed to assemble cartridge '{}': {}", location, e))?;
+
+    Ok(Cartridge {
+        program: program.bytes,
+        options: parse_options(&json["options"]),
+    })
+}
+
+fn parse_options(options: &Value) -> Options {
+    let mut quirks = build_quirks();
+    let flags = [
+        ("shiftQuirks", &mut quirks.shift),
+        ("loadStoreQuirks", &mut quirks.load_store),
+        ("jumpQuirks", &mut quirks.jump),
+        ("logicQuirks", &mut quirks.logic),
+        ("clipQuirks", &mut quirks.clip),
+        ("vBlankQuirks", &mut quirks.display_wait),
+    ];
+    for (key, quirk) in flags {
+        if let Some(value) = options[key].as_bool() {
+            *quirk = value;
+        }
+    }
+
+    Options {
+        tickrate: options["tickrate"].as_u64().map(|t| t as usize),
+        palette: parse_palette(options),
+        quirks,
+    }
+}
+
+// Octo colors pixels by plane: background, fill, fill2 and blend for both
+fn parse_palette(options: &Value) -> Option<Palette> {
+    let keys = ["backgroundColor", "fillColor", "fillColor2", "blendColor"];
+    let defaults = ["#996600", "#FFCC00", "#FF6600", "#662200"];
+    if keys.iter().all(|key| options[key].is_null()) {
+        return None;
+    }
+
+    let colors = keys
+        .iter()
+        .zip(defaults)
+        .map(|(key, default)| {
+            let color = options[key].as_str().and_then(parse_color);
+            color.or_else(|| parse_color(default)).unwrap()
+        })
+        .collect();
+    build_palette("cartridge", colors).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const WIDTH: u16 = 32;
+
+    // Spreads the payload over the low bits of a four-color GIF the way Octo does
+    fn build_gif(json: &str) -> Vec<u8> {
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(json.as_bytes());
+        let mut pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|&byte| [byte >> 6, byte >> 4, byte >> 2, byte].map(|bits| bits & 0x3))
+            .collect();
+        let height = pixels.len().div_ceil(WIDTH as usize) as u16;
+        pixels.resize(WIDTH as usize * height as usize, 0);
+
+        let mut gif = Vec::new();
+        let palette = [0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255];
+        let mut encoder = gif::Encoder::new(&mut gif, WIDTH, height, &palette).unwrap();
+        let frame = gif::Frame {
+            width: WIDTH,
+            height,
+            buffer: pixels.into(),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+        gif
+    }
+
+    #[test]
+    fn programs_and_options_are_read_from_the_gif() {
+        let json = r#"{
+            "program": ": main\n  v0 := 5\n  jump main\n",
+            "options": {
+                "tickrate": 20,
+                "shiftQuirks": true,
+                "loadStoreQuirks": true,
+                "vBlankQuirks": false
+            }
+        }"#;
+        let cartridge = read_cartridge(Cursor::new(build_gif(json)), "test.gif").unwrap();
+
+        assert_eq!(cartridge.program, [0x60, 0x05, 0x12, 0x00]);
+        assert_eq!(cartridge.options.tickrate, Some(20));
+        assert!(cartridge.options.palette.is_none());
+        let quirks = cartridge.options.quirks;
+        assert!(quirks.shift);
+        assert!(quirks.load_store);
+        assert!(!quirks.display_wait);
+        assert_eq!(quirks.jump, build_quirks().jump);
+    }
+
+    #[test]
+    fn gifs_without_a_payload_are_rejected() {
+        let mut gif = build_gif("{}");
+        gif.truncate(gif.len() / 2);
+        assert!(read_cartridge(Cursor::new(gif), "test.gif").is_err());
+        let cartridge = read_cartridge(Cursor::new(build_gif("{}")), "test.gif");
+        assert!(cartridge.is_err_and(|e| e.contains("no program")));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::crt::{Effects, parse_effects};
+use crate::phosphor::{Persistence, parse_persistence};
+use crate::platform::{Platform, parse_platform};
+use crate::scaling::{ScaleMode, parse_scale_mode};
+use crate::trace::{
+    TraceOptions, build_trace_options, parse_opcode_classes, parse_range, parse_trace_format,
+};
+
+pub const USAGE: &str = "Usage: rust-chip8 [OPTIONS] [ROM]
+       rust-chip8 diff-frames <LOG A> <LOG B>
+       rust-chip8 assemble <SOURCE> <ROM>
+       rust-chip8 dap
+       rust-chip8 bench <ROM> [INSTRUCTIONS]
+
+Options:
+  --platform <NAME>    Interpreter the ROM was written for: chip8, chip8x,
+                       chip8-hires, megachip, schip1.0 or schip1.1
+                       (default chip8)
+  --palette <NAME>     Start with a built-in palette (classic, amber, lcd, octo, high-contrast)
+  --colors <HEX,...>   Use a custom palette of 2, 4 or 16 colors, e.g. #000000,#FFFFFF
+  --ghosting <MODE>    Reduce flicker: off, max2, decay or decay:<0..1> (default off)
+  --crt <EFFECTS>      Comma-separated CRT effects: scanlines, grid, bloom, curvature, vignette, all
+  --scale-mode <MODE>  Window scaling: integer (letterboxed), fit or stretch (default integer)
+  --fullscreen         Start in borderless fullscreen
+  --headless           Run without a window for --frames frames, then exit
+  --frames <N>         Number of frames to run in headless mode (default 600)
+  --block-cache        Run cached blocks of decoded instructions back to back (faster)
+  --screenshot <PATH>  Save a PNG of the display on exit
+  --record <PATH>      Record frames to an animated .gif, or a raw RGBA stream for ffmpeg
+  --capture-scale <N>  Pixel scale for screenshots and recordings (default 10)
+  --frame-log <PATH>   Log every frame's display and executed instructions for diff-frames
+  --trace <PATH>       Log executed instructions to a file, or - for stderr
+  --trace-format <F>   Trace format: text or binary (default text)
+  --trace-pc <RANGE>   Only trace instructions in an address range, e.g. 0x200-0x2FF
+  --trace-ops <LIST>   Only trace opcode classes by first nibble, e.g. 1,2,D
+  --trace-frames <R>   Only trace frames in a range, e.g. 100-200
+  --trace-ring <N>     Keep the last N instructions in memory and dump them on a fault
+  --profile <PATH>     Write an execution profile on exit, or - for stdout
+  --profile-folded <P> Also write folded call stacks for flamegraph.pl or inferno
+  --coverage <PATH>    Write an annotated listing of executed, sprite and data bytes on exit
+  --coverage-map <P>   Save a PNG heatmap of memory usage on exit
+  --gdb <PORT>         Run without a window and wait for a GDB remote connection on localhost
+  --symbols <PATH>     Load labels and source lines from a symbol file (default <ROM>.sym if present)
+  --help               Show this message";
+
+pub struct Config {
+    pub rom_path: Option<String>,
+    pub platform: Platform,
+    pub palette: Option<String>,
+    pub colors: Option<String>,
+    pub ghosting: Persistence,
+    pub crt: Effects,
+    pub scale_mode: ScaleMode,
+    pub fullscreen: bool,
+    pub headless: bool,
+    pub frames: usize,
+    pub block_cache: bool,
+    pub screenshot: Option<String>,
+    pub record: Option<String>,
+    pub capture_scale: usize,
+    pub frame_log: Option<String>,
+    pub trace: Option<TraceOptions>,
+    pub profile: Option<String>,
+    pub profile_folded: Option<String>,
+    pub coverage: Option<String>,
+    pub coverage_map: Option<String>,
+    pub gdb: Option<u16>,
+    pub symbols: Option<String>,
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Config, String> {
+    let mut config = Config {
+        rom_path: None,
+        platform: Platform::Chip8,
+        palette: None,
+        colors: None,
+        ghosting: Persistence::Off,
+        crt: Effects::default(),
+        scale_mode: ScaleMode::Integer,
+        fullscreen: false,
+        headless: false,
+        frames: 600,
+        block_cache: false,
+        screenshot: None,
+        record: None,
+        capture_scale: 10,
+        frame_log: None,
+        trace: None,
+        profile: None,
+        profile_folded: None,
+        coverage: None,
+        coverage_map: None,
+        gdb: None,
+        symbols: None,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => config.platform = parse_platform(&value(&mut args, &arg)?)?,
+            "--palette" => config.palette = Some(value(&mut args, &arg)?),
+            "--colors" => config.colors = Some(value(&mut args, &arg)?),
+            "--ghosting" => config.ghosting = parse_persistence(&value(&mut args, &arg)?)?,
+            "--crt" => config.crt = parse_effects(&value(&mut args, &arg)?)?,
+            "--scale-mode" => config.scale_mode = parse_scale_mode(&value(&mut args, &arg)?)?,
+            "--fullscreen" => config.fullscreen = true,
+            "--headless" => config.headless = true,
+            "--frames" => config.frames = number(&mut args, &arg)?,
+            "--block-cache" => config.block_cache = true,
+            "--screenshot" => config.screenshot = Some(value(&mut args, &arg)?),
+            "--record" => config.record = Some(value(&mut args, &arg)?),
+            "--capture-scale" => config.capture_scale = number(&mut args, &arg)?.max(1),
+            "--frame-log" => config.frame_log = Some(value(&mut args, &arg)?),
+            "--trace" => trace_options(&mut config).output = Some(value(&mut args, &arg)?),
+            "--trace-format" => {
+                trace_options(&mut config).format = parse_trace_format(&value(&mut args, &arg)?)?
+            }
+            "--trace-pc" => {
+                trace_options(&mut config).pc_range = Some(parse_range(&value(&mut args, &arg)?)?)
+            }
+            "--trace-ops" => {
+                let classes = parse_opcode_classes(&value(&mut args, &arg)?)?;
+                trace_options(&mut config).opcode_classes = Some(classes);
+            }
+            "--trace-frames" => {
+                let range = parse_range(&value(&mut args, &arg)?)?;
+                trace_options(&mut config).frame_range = Some(range);
+            }
+            "--trace-ring" => {
+                trace_options(&mut config).ring_size = Some(number(&mut args, &arg)?.max(1))
+            }
+            "--profile" => config.profile = Some(value(&mut args, &arg)?),
+            "--profile-folded" => config.profile_folded = Some(value(&mut args, &arg)?),
+            "--coverage" => config.coverage = Some(value(&mut args, &arg)?),
+            "--coverage-map" => config.coverage_map = Some(value(&mut args, &arg)?),
+            "--symbols" => config.symbols = Some(value(&mut args, &arg)?),
+            "--gdb" => {
+                let port = number(&mut args, &arg)?;
+                let port = u16::try_from(port).map_err(|_| format!("Invalid port {}", port))?;
+                config.gdb = Some(port);
+            }
+            "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ => config.rom_path = Some(arg),
+        }
+    }
+
+    Ok(config)
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Option '{}' requires a value", flag))
+}
+
+fn number(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<usize, String> {
+    let text = value(args, flag)?;
+    text.parse()
+        .map_err(|_| format!("Option '{}' expects a number, got '{}'", flag, text))
+}
+
+fn trace_options(config: &mut Config) -> &mut TraceOptions {
+    config.trace.get_or_insert_with(build_trace_options)
+}
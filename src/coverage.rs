@@ -0,0 +1,188 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::capture::save_png;
+use crate::disassembler::disassemble;
+use crate::platform::Platform;
+use crate::symbols::Symbols;
+
+pub const EXECUTED: u8 = 1;
+pub const SPRITE: u8 = 2;
+pub const READ: u8 = 4;
+pub const WRITTEN: u8 = 8;
+
+const MAP_WIDTH: usize = 64;
+const MAP_HEIGHT: usize = 64;
+const UNTOUCHED_COLOR: [u8; 3] = [0x10, 0x10, 0x18];
+
+// Per-byte record of how memory was used. Bytes can carry several flags,
+// e.g. self-modifying code is both executed and written.
+pub struct Coverage {
+    flags: Vec<u8>,
+    counts: Vec<u32>,
+}
+
+pub fn build_coverage() -> Coverage {
+    Coverage {
+        flags: vec![0; 4096],
+        counts: vec![0; 4096],
+    }
+}
+
+impl Coverage {
+    pub fn mark(&mut self, address: usize, length: usize, kind: u8) {
+        for offset in 0..length {
+            let address = (address + offset) % self.flags.len();
+            self.flags[address] |= kind;
+            self.counts[address] = self.counts[address].saturating_add(1);
+        }
+    }
+
+    // Executed bytes are listed as instructions, other touched bytes as data
+    // with their bit pattern, and untouched runs are collapsed.
+    pub fn write_listing(
+        &self,
+        memory: &[u8],
+        platform: Platform,
+        symbols: Option<&Symbols>,
+        writer: &mut impl Write,
+    ) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "; flags: X executed, S sprite data, R read by load, W written"
+        )?;
+        let label = |address: usize| {
+            symbols
+                .and_then(|symbols| symbols.name(address as u16))
+                .filter(|name| !name.contains('+'))
+        };
+        // Coverage tracks the 4K that CHIP-8 addresses can reach
+        let memory = &memory[..memory.len().min(self.flags.len())];
+        let mut address = 0;
+        while address < memory.len() {
+            let flags = self.flags[address];
+            if let Some(name) = label(address) {
+                writeln!(writer, "{}:", name)?;
+            }
+            if flags == 0 {
+                let start = address;
+                address += 1;
+                while address < memory.len() && self.flags[address] == 0 && label(address).is_none()
+                {
+                    address += 1;
+                }
+                let nonzero = memory[start..address].iter().filter(|&&b| b != 0).count();
+                writeln!(
+                    writer,
+                    "; {:03X}-{:03X} untouched ({} bytes, {} non-zero)",
+                    start,
+                    address - 1,
+                    address - start,
+                    nonzero
+                )?;
+                continue;
+            }
+
+            if flags & EXECUTED != 0 && address + 1 < memory.len() {
+                let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+                writeln!(
+                    writer,
+                    "{:03X}: {:04X}      {} {:8}  {}",
+                    address,
+                    opcode,
+                    flag_string(flags | self.flags[address + 1]),
+                    self.counts[address],
+                    disassemble(opcode, platform, symbols)
+                )?;
+                address += 2;
+                continue;
+            }
+
+            let bits: String = (0..8)
+                .map(|bit| {
+                    if memory[address] & (0x80 >> bit) != 0 {
+                        '#'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            writeln!(
+                writer,
+                "{:03X}: {:02X} {} {} {:8}",
+                address,
+                memory[address],
+                bits,
+                flag_string(flags),
+                self.counts[address]
+            )?;
+            address += 1;
+        }
+        Ok(())
+    }
+
+    // One pixel per byte, 64 bytes per row. The hue shows how the byte was
+    // used and the brightness how often, on a log scale.
+    pub fn heatmap(&self) -> Vec<u8> {
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let mut frame = Vec::with_capacity(MAP_WIDTH * MAP_HEIGHT * 4);
+        for (&flags, &count) in self.flags.iter().zip(&self.counts) {
+            let color = if flags == 0 {
+                UNTOUCHED_COLOR
+            } else {
+                let base = if flags & WRITTEN != 0 {
+                    [0xFF, 0x40, 0x40]
+                } else if flags & EXECUTED != 0 {
+                    [0x40, 0xFF, 0x60]
+                } else if flags & SPRITE != 0 {
+                    [0x40, 0x90, 0xFF]
+                } else {
+                    [0xFF, 0xD0, 0x40]
+                };
+                let intensity = 0.3 + 0.7 * (1.0 + count as f32).ln() / (1.0 + max).ln();
+                base.map(|c| (c as f32 * intensity) as u8)
+            };
+            frame.extend_from_slice(&[color[0], color[1], color[2], 255]);
+        }
+        frame
+    }
+}
+
+fn flag_string(flags: u8) -> String {
+    [(EXECUTED, 'X'), (SPRITE, 'S'), (READ, 'R'), (WRITTEN, 'W')]
+        .iter()
+        .map(|&(flag, c)| if flags & flag != 0 { c } else { '-' })
+        .collect()
+}
+
+pub fn save_reports(
+    coverage: &Coverage,
+    memory: &[u8],
+    platform: Platform,
+    symbols: Option<&Symbols>,
+    listing: Option<&str>,
+    map: Option<&str>,
+    scale: usize,
+) {
+    if let Some(location) = listing {
+        let result = File::create(location).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            coverage.write_listing(memory, platform, symbols, &mut writer)?;
+            writer.flush()
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to write coverage listing '{}': {}", location, e);
+        }
+    }
+
+    if let Some(location) = map
+        && let Err(e) = save_png(
+            location,
+            &coverage.heatmap(),
+            (MAP_WIDTH, MAP_HEIGHT),
+            scale,
+        )
+    {
+        eprintln!("{}", e);
+    }
+}
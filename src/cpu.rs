@@ -1,8 +1,24 @@
 use std::fs;
+use std::rc::Rc;
 
 use rand::Rng;
 
-use crate::display::{Display, build_display};
+use crate::blocks::{Block, MAX_BLOCK_LENGTH, decode_block};
+use crate::callstack::{CallStack, Frame, build_call_stack};
+use crate::coverage::{self, Coverage};
+use crate::display::{ColorMap, Display, Rect, Snapshot, WIDTH, ZONE_WIDTH, build_display};
+use crate::instruction::{Instruction, decode_for, encode};
+use crate::megachip::{self, MegaScreen, Sample, blend_mode, build_mega_screen, read_sample};
+use crate::platform::Platform;
+use crate::profiler::Profiler;
+use crate::quirks::{Quirks, build_quirks};
+use crate::trace::{TraceEntry, Tracer};
+
+// Instructions are cached for every address pc can hold, which on
+// MegaChip is less than all of memory
+const CODE_SIZE: usize = 0x10000;
+// I is 24 bits wide on MegaChip
+const I_MASK: u32 = 0xFFFFFF;
 
 const FONT_SET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -23,37 +39,100 @@ const FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// SCHIP's 8x10 digits for FX30, stored after the small font
+const BIG_FONT_START: usize = 0x50;
+const BIG_FONT_SET: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+#[derive(Clone, Copy)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: u32,
+    pub pc: u16,
+    pub sp: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
 pub struct Cpu {
-    memory: [u8; 4096],
+    memory: Vec<u8>,
+    decoded: Vec<Option<Result<Instruction, u16>>>,
+    decode_cache: bool,
+    blocks: Vec<Option<Rc<Block>>>,
+    block_cache: bool,
     stack: [u16; 16],
     v_registers: [u8; 16],
-    i_register: u16,
+    i_register: u32,
     pc: u16,
     sp: usize,
+    platform: Platform,
     display: Display,
-    display_wait_quirk: bool,
+    mega: Option<MegaScreen>,
+    sample: Option<Sample>,
+    // SCHIP's 128x64 mode, as opposed to drawing in 2x2 blocks
+    hires: bool,
+    // The HP-48 RPL user flags that SCHIP saves registers to
+    rpl_flags: [u8; 8],
+    quirks: Quirks,
     draw_occurred_this_frame: bool,
     keys: [u8; 16],
+    second_keys: [u8; 16],
     key_pressed_while_waiting: Option<u8>,
+    output_port: u8,
+    input_port: Option<u8>,
     delay_timer: u8,
     sound_timer: u8,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    call_stack: CallStack,
+    fault: Option<String>,
+    halted: bool,
 }
 
 pub fn build_cpu() -> Cpu {
     let mut cpu = Cpu {
-        memory: [0; 4096],
+        memory: vec![0; 0x1000],
+        decoded: vec![None; 0x1000],
+        decode_cache: true,
+        blocks: vec![None; 0x1000],
+        block_cache: false,
         stack: [0; 16],
         v_registers: [0; 16],
         i_register: 0,
         pc: 0x200,
         sp: 0,
+        platform: Platform::Chip8,
         display: build_display(),
-        display_wait_quirk: true,
+        mega: None,
+        sample: None,
+        hires: false,
+        rpl_flags: [0; 8],
+        quirks: build_quirks(),
         draw_occurred_this_frame: false,
         keys: [0; 16],
+        second_keys: [0; 16],
         key_pressed_while_waiting: None,
+        output_port: 0,
+        input_port: None,
         delay_timer: 0,
         sound_timer: 0,
+        tracer: None,
+        profiler: None,
+        coverage: None,
+        call_stack: build_call_stack(),
+        fault: None,
+        halted: false,
     };
     cpu.load_font_data();
     cpu
@@ -61,93 +140,463 @@ pub fn build_cpu() -> Cpu {
 
 impl Cpu {
     pub fn cycle(&mut self) {
-        if self.display_wait_quirk && self.draw_occurred_this_frame {
+        if self.halted {
+            return;
+        }
+        if self.is_waiting_for_display() {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record_display_wait();
+            }
+            return;
+        }
+
+        let pc = self.pc;
+        if pc as usize + 1 >= self.memory.len() {
+            self.raise_fault(format!("PC {:#05x} is outside memory", pc));
+            return;
+        }
+        let instruction = self.instruction_at(pc);
+        let opcode =
+            (self.tracer.is_some() || self.profiler.is_some()).then(|| self.fetch_instruction());
+        self.cover(pc as usize, 2, coverage::EXECUTED);
+        let registers = self.v_registers;
+        self.fault = None;
+
+        self.execute(instruction);
+
+        if let Some(opcode) = opcode {
+            if self.tracer.is_some() {
+                self.trace(pc, opcode, registers);
+            }
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record(pc, opcode, self.pc);
+            }
+        }
+    }
+
+    // Runs up to the given number of cycles and returns how many of them
+    // executed an instruction. With the block cache on, whole blocks of
+    // decoded instructions run back to back; debugging attachments need every
+    // cycle, so they fall back to running one at a time.
+    pub fn run(&mut self, cycles: usize) -> usize {
+        if !self.block_cache
+            || self.tracer.is_some()
+            || self.profiler.is_some()
+            || self.coverage.is_some()
+        {
+            let mut executed = 0;
+            for _ in 0..cycles {
+                if self.halted {
+                    break;
+                }
+                if !self.is_waiting_for_display() {
+                    executed += 1;
+                }
+                self.cycle();
+            }
+            return executed;
+        }
+
+        let mut remaining = cycles;
+        while remaining > 0 && !self.halted && !self.is_waiting_for_display() {
+            let block = self.block_at(self.pc);
+            if block.instructions.is_empty() {
+                self.cycle();
+                remaining -= 1;
+                continue;
+            }
+            self.fault = None;
+            let count = block.instructions.len().min(remaining);
+            for &instruction in &block.instructions[..count] {
+                self.execute(instruction);
+            }
+            remaining -= count;
+        }
+        cycles - remaining
+    }
+
+    // Past the end of memory the block is empty, and cycle raises the fault
+    fn block_at(&mut self, pc: u16) -> Rc<Block> {
+        if let Some(Some(block)) = self.blocks.get(pc as usize) {
+            return block.clone();
+        }
+        let block = Rc::new(decode_block(&self.memory, pc, self.platform));
+        if let Some(entry) = self.blocks.get_mut(pc as usize) {
+            *entry = Some(block.clone());
+        }
+        block
+    }
+
+    fn trace(&mut self, pc: u16, opcode: u16, registers: [u8; 16]) {
+        let Some(tracer) = &mut self.tracer else {
             return;
+        };
+
+        if tracer.wants(pc, opcode) {
+            let changed = (0..16)
+                .filter(|&r| self.v_registers[r] != registers[r])
+                .map(|r| (r, self.v_registers[r]))
+                .collect();
+            tracer.record(TraceEntry {
+                frame: tracer.frame(),
+                pc,
+                opcode,
+                changed,
+                i_register: self.i_register,
+                delay_timer: self.delay_timer,
+                sound_timer: self.sound_timer,
+            });
         }
 
+        if let Some(fault) = &self.fault {
+            tracer.report_fault(fault);
+        }
+    }
+
+    pub fn attach_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    pub fn attach_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn attach_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    fn cover(&mut self, address: usize, length: usize, kind: u8) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark(address, length, kind);
+        }
+    }
+
+    pub fn is_waiting_for_display(&self) -> bool {
+        self.quirks.display_wait && self.draw_occurred_this_frame
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn i_register(&self) -> u32 {
+        self.i_register
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            v: self.v_registers,
+            i: self.i_register,
+            pc: self.pc,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.v_registers = registers.v;
+        self.i_register = registers.i & I_MASK;
+        self.pc = registers.pc & 0x0FFF;
+        self.sp = registers.sp.min(self.stack.len());
+        self.call_stack.sync(&self.stack[..self.sp]);
+        self.delay_timer = registers.delay_timer;
+        self.sound_timer = registers.sound_timer;
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    // Frames of the active subroutine calls, outermost first
+    pub fn call_stack(&self) -> &[Frame] {
+        self.call_stack.frames()
+    }
+
+    pub fn take_stack_warnings(&mut self) -> Vec<String> {
+        self.call_stack.take_warnings()
+    }
+
+    // Why the last instruction could not be executed, if it could not
+    pub fn fault(&self) -> Option<&str> {
+        self.fault.as_deref()
+    }
+
+    // Whether the program has ended with SCHIP's 00FD. Nothing runs after it.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn write_memory(&mut self, address: usize, value: u8) {
+        self.memory[address] = value;
+        self.invalidate(address, 1);
+    }
+
+    // Turning the cache off decodes every instruction as it is fetched
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled;
+        self.invalidate(0, self.memory.len());
+    }
+
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.block_cache = enabled;
+        self.invalidate(0, self.memory.len());
+    }
+
+    // Invalid opcodes are kept as errors holding the opcode
+    fn instruction_at(&mut self, pc: u16) -> Result<Instruction, u16> {
+        if self.decode_cache
+            && let Some(&Some(instruction)) = self.decoded.get(pc as usize)
+        {
+            return instruction;
+        }
         let opcode = self.fetch_instruction();
-        self.execute_instruction(opcode);
+        let instruction = decode_for(self.platform, opcode).map_err(|_| opcode);
+        if self.decode_cache
+            && let Some(entry) = self.decoded.get_mut(pc as usize)
+        {
+            *entry = Some(instruction);
+        }
+        instruction
     }
 
+    // An instruction starting one byte before a written range overlaps it,
+    // and so does any block starting less than a block's length before it
+    fn invalidate(&mut self, address: usize, length: usize) {
+        if address >= self.decoded.len() {
+            return;
+        }
+        let end = (address + length).min(self.decoded.len());
+        self.decoded[address.saturating_sub(1)..end].fill(None);
+
+        let first = address.saturating_sub(MAX_BLOCK_LENGTH * 2 - 1);
+        for start in first..end {
+            if let Some(block) = &self.blocks[start]
+                && start + block.instructions.len() * 2 > address
+            {
+                self.blocks[start] = None;
+            }
+        }
+    }
+
+    // Reads as zero past the end of memory, where cycle raises a fault
     pub fn fetch_instruction(&self) -> u16 {
-        let high_byte: u16 = self.memory[self.pc as usize] as u16;
-        let low_byte: u16 = self.memory[self.pc as usize + 1] as u16;
-        (high_byte << 8) | low_byte
-    }
-
-    pub fn execute_instruction(&mut self, opcode: u16) {
-        let nibbles = (
-            ((opcode & 0xF000) >> 12) as u8,
-            ((opcode & 0x0F00) >> 8) as u8,
-            ((opcode & 0x00F0) >> 4) as u8,
-            (opcode & 0x000F) as u8,
-        );
-
-        let nnn = opcode & 0x0FFF;
-        let nn = (opcode & 0x00FF) as u8;
-        let x = nibbles.1 as usize;
-        let y = nibbles.2 as usize;
-        let n = nibbles.3;
-
-        match nibbles {
-            (0x0, 0x0, 0xE, 0x0) => self.clear_screen(),
-            (0x0, 0x0, 0xE, 0xE) => self.return_from_subroutine(),
-            (0x1, _, _, _) => self.jump(nnn),
-            (0x2, _, _, _) => self.call_subroutine(nnn),
-            (0x3, _, _, _) => self.skip_if_vx_equals(x, nn),
-            (0x4, _, _, _) => self.skip_if_vx_not_equals(x, nn),
-            (0x5, _, _, 0x0) => self.skip_if_vx_equals_vy(x, y),
-            (0x6, _, _, _) => self.set_vx(x, nn),
-            (0x7, _, _, _) => self.add_to_vx(x, nn),
-            (0x8, _, _, 0x0) => self.set_vx_to_vy(x, y),
-            (0x8, _, _, 0x1) => self.set_vx_to_vx_or_vy(x, y),
-            (0x8, _, _, 0x2) => self.set_vx_to_vx_and_vy(x, y),
-            (0x8, _, _, 0x3) => self.set_vx_to_vx_xor_vy(x, y),
-            (0x8, _, _, 0x4) => self.add_vy_to_vx(x, y),
-            (0x8, _, _, 0x5) => self.sub_vy_from_vx(x, y),
-            (0x8, _, _, 0x6) => self.shift_vx_right(x, y),
-            (0x8, _, _, 0x7) => self.set_vx_to_vy_minus_vx(x, y),
-            (0x8, _, _, 0xE) => self.shift_vx_left(x, y),
-            (0x9, _, _, 0x0) => self.skip_if_vx_not_equals_vy(x, y),
-            (0xA, _, _, _) => self.set_i(nnn),
-            (0xB, _, _, _) => self.jump_to_v0_plus_nnn(nnn),
-            (0xC, _, _, _) => self.random(x, nn),
-            (0xD, _, _, _) => self.draw_sprite(x, y, n),
-            (0xE, _, 0x9, 0xE) => self.skip_if_key_pressed(x),
-            (0xE, _, 0xA, 0x1) => self.skip_if_key_not_pressed(x),
-            (0xF, _, 0x0, 0x7) => self.set_vx_to_delay_timer(x),
-            (0xF, _, 0x0, 0xA) => self.wait_for_key(x),
-            (0xF, _, 0x1, 0x5) => self.set_delay_timer(x),
-            (0xF, _, 0x1, 0x8) => self.set_sound_timer(x),
-            (0xF, _, 0x1, 0xE) => self.add_vx_to_i(x),
-            (0xF, _, 0x2, 0x9) => self.set_i_to_sprite(x),
-            (0xF, _, 0x3, 0x3) => self.store_bcd(x),
-            (0xF, _, 0x5, 0x5) => self.store_registers(x),
-            (0xF, _, 0x6, 0x5) => self.load_registers(x),
-            _ => eprintln!("Unknown opcode: {:#06x}", opcode),
-        }
-    }
-
-    pub fn load_rom(&mut self, location: &str) {
-        let rom = fs::read(location).unwrap_or_else(|e| {
-            panic!(
+        let byte = |address: usize| self.memory.get(address).copied().unwrap_or(0) as u16;
+        byte(self.pc as usize) << 8 | byte(self.pc as usize + 1)
+    }
+
+    fn execute(&mut self, instruction: Result<Instruction, u16>) {
+        let instruction = match instruction {
+            Ok(instruction) => instruction,
+            Err(opcode) => return self.unknown_opcode(opcode),
+        };
+        match instruction {
+            // Machine code routines are not supported
+            Instruction::Sys(_) => self.unknown_opcode(encode(instruction)),
+            Instruction::Cls => self.clear_screen(),
+            Instruction::Ret => self.return_from_subroutine(),
+            Instruction::Jp(nnn) => self.jump(nnn),
+            Instruction::Call(nnn) => self.call_subroutine(nnn),
+            Instruction::SeByte { x, nn } => self.skip_if_vx_equals(x as usize, nn),
+            Instruction::SneByte { x, nn } => self.skip_if_vx_not_equals(x as usize, nn),
+            Instruction::SeReg { x, y } => self.skip_if_vx_equals_vy(x as usize, y as usize),
+            Instruction::LdByte { x, nn } => self.set_vx(x as usize, nn),
+            Instruction::AddByte { x, nn } => self.add_to_vx(x as usize, nn),
+            Instruction::LdReg { x, y } => self.set_vx_to_vy(x as usize, y as usize),
+            Instruction::Or { x, y } => self.set_vx_to_vx_or_vy(x as usize, y as usize),
+            Instruction::And { x, y } => self.set_vx_to_vx_and_vy(x as usize, y as usize),
+            Instruction::Xor { x, y } => self.set_vx_to_vx_xor_vy(x as usize, y as usize),
+            Instruction::AddReg { x, y } => self.add_vy_to_vx(x as usize, y as usize),
+            Instruction::Sub { x, y } => self.sub_vy_from_vx(x as usize, y as usize),
+            Instruction::Shr { x, y } => self.shift_vx_right(x as usize, y as usize),
+            Instruction::Subn { x, y } => self.set_vx_to_vy_minus_vx(x as usize, y as usize),
+            Instruction::Shl { x, y } => self.shift_vx_left(x as usize, y as usize),
+            Instruction::SneReg { x, y } => self.skip_if_vx_not_equals_vy(x as usize, y as usize),
+            Instruction::LdI(nnn) => self.set_i(nnn),
+            Instruction::JpV0(nnn) => self.jump_with_offset((nnn >> 8) as usize, nnn),
+            Instruction::Rnd { x, nn } => self.random(x as usize, nn),
+            Instruction::Drw { x, y, n } => self.draw_sprite(x as usize, y as usize, n),
+            Instruction::Skp(x) => self.skip_if_key_pressed(x as usize),
+            Instruction::Sknp(x) => self.skip_if_key_not_pressed(x as usize),
+            Instruction::LdVxDt(x) => self.set_vx_to_delay_timer(x as usize),
+            Instruction::LdVxK(x) => self.wait_for_key(x as usize),
+            Instruction::LdDtVx(x) => self.set_delay_timer(x as usize),
+            Instruction::LdStVx(x) => self.set_sound_timer(x as usize),
+            Instruction::AddI(x) => self.add_vx_to_i(x as usize),
+            Instruction::LdF(x) => self.set_i_to_sprite(x as usize),
+            Instruction::LdB(x) => self.store_bcd(x as usize),
+            Instruction::LdIVx(x) => self.store_registers(x as usize),
+            Instruction::LdVxI(x) => self.load_registers(x as usize),
+            Instruction::Bgc => self.cycle_background(),
+            Instruction::AddNib { x, y } => self.add_nibbles(x as usize, y as usize),
+            Instruction::Col { x, y, n } => self.set_zone_color(x as usize, y as usize, n),
+            Instruction::Skp2(x) => self.skip_if_second_key_pressed(x as usize),
+            Instruction::Sknp2(x) => self.skip_if_second_key_not_pressed(x as usize),
+            Instruction::Out(x) => self.output(x as usize),
+            Instruction::In(x) => self.wait_for_input(x as usize),
+            Instruction::MegaOff => self.set_mega_mode(false),
+            Instruction::MegaOn => self.set_mega_mode(true),
+            Instruction::ScrollUp(n) => self.mega_op(|mega, _| mega.scroll_up(n as usize)),
+            Instruction::LdHi(nn) => self.set_i_long(nn),
+            Instruction::LdPal(nn) => {
+                let i = self.i_register as usize;
+                self.mega_op(|mega, memory| mega.load_palette(memory, i, nn as usize))
+            }
+            Instruction::SprW(nn) => self.mega_op(|mega, _| mega.set_sprite_width(nn)),
+            Instruction::SprH(nn) => self.mega_op(|mega, _| mega.set_sprite_height(nn)),
+            Instruction::Alpha(nn) => self.mega_op(|mega, _| mega.set_alpha(nn)),
+            Instruction::DigiSnd(n) => self.select_sample(n),
+            Instruction::StopSnd => {
+                self.sample = None;
+                self.pc += 2
+            }
+            Instruction::BMode(n) => match blend_mode(n) {
+                Some(blend) => self.mega_op(|mega, _| mega.set_blend(blend)),
+                None => self.unknown_opcode(encode(instruction)),
+            },
+            Instruction::CCol(nn) => self.mega_op(|mega, _| mega.set_collision_color(nn)),
+            Instruction::ScrollDown(n) => self.scroll(|display| display.scroll_down(n as usize)),
+            Instruction::ScrollRight => self.scroll(|display| display.scroll_right(4)),
+            Instruction::ScrollLeft => self.scroll(|display| display.scroll_left(4)),
+            Instruction::Exit => self.halted = true,
+            Instruction::Low => self.set_hires(false),
+            Instruction::High => self.set_hires(true),
+            Instruction::LdHf(x) => self.set_i_to_big_sprite(x as usize),
+            Instruction::LdRVx(x) => self.store_rpl_flags(x as usize),
+            Instruction::LdVxR(x) => self.load_rpl_flags(x as usize),
+        }
+    }
+
+    fn unknown_opcode(&mut self, opcode: u16) {
+        self.raise_fault(format!(
+            "Unknown opcode {:#06x} at {:#05x}",
+            opcode, self.pc
+        ));
+    }
+
+    pub fn load_rom(&mut self, location: &str) -> Result<(), String> {
+        let rom = fs::read(location).map_err(|e| {
+            format!(
                 "Failed to read ROM file at '{}': {}. Please ensure the file exists.",
                 location, e
+            )
+        })?;
+        if rom.len() > self.memory.len() - self.platform.load_address() as usize {
+            return Err(format!(
+                "ROM '{}' is {} bytes, which does not fit in memory",
+                location,
+                rom.len()
+            ));
+        }
+        self.load_program(&rom);
+        Ok(())
+    }
+
+    pub fn load_program(&mut self, program: &[u8]) {
+        let start = self.platform.load_address() as usize;
+        if program.len() > self.memory.len() - start {
+            panic!(
+                "Program is {} bytes, which does not fit in memory",
+                program.len()
             );
-        });
-        self.memory[0x200..(rom.len() + 0x200)].copy_from_slice(&rom[..]);
+        }
+        self.memory[start..(program.len() + start)].copy_from_slice(program);
+        self.invalidate(start, program.len());
+    }
+
+    // Has to be chosen before the program is loaded, since platforms load
+    // programs at different addresses
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.pc = platform.entry_point();
+        let (width, height) = platform.display_size();
+        self.display.set_size(width, height);
+        self.quirks = platform.quirks();
+        self.hires = false;
+        self.memory.resize(platform.memory_size(), 0);
+        let code_size = self.memory.len().min(CODE_SIZE);
+        self.decoded = vec![None; code_size];
+        self.blocks = vec![None; code_size];
+        match platform {
+            Platform::Chip8X => self.display.enable_colors(),
+            Platform::MegaChip => self.mega = Some(build_mega_screen()),
+            _ => {}
+        }
+        self.invalidate(0, self.memory.len());
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
     }
 
     pub fn load_font_data(&mut self) {
         self.memory[0x0..FONT_SET.len()].copy_from_slice(&FONT_SET[..]);
+        self.memory[BIG_FONT_START..BIG_FONT_START + BIG_FONT_SET.len()]
+            .copy_from_slice(&BIG_FONT_SET[..]);
+        self.invalidate(0, BIG_FONT_START + BIG_FONT_SET.len());
     }
 
-    pub fn get_display(&self) -> [[u8; 64]; 32] {
-        self.display.get_display()
+    pub fn get_display(&self) -> Snapshot {
+        match self.mega_screen() {
+            Some(mega) => mega.snapshot(),
+            None => self.display.get_display(),
+        }
+    }
+
+    // Can change while running, when a MegaChip program switches modes
+    pub fn display_size(&self) -> (usize, usize) {
+        match self.mega_screen() {
+            Some(_) => (megachip::WIDTH, megachip::HEIGHT),
+            None => (self.display.width(), self.display.height()),
+        }
+    }
+
+    // The MegaChip screen, while MegaChip mode is on
+    pub fn mega_screen(&self) -> Option<&MegaScreen> {
+        self.mega.as_ref().filter(|mega| mega.enabled())
+    }
+
+    // The digitised sound a MegaChip program last started, if any. Nothing
+    // plays it yet.
+    pub fn sample(&self) -> Option<Sample> {
+        self.sample
+    }
+
+    pub fn colors(&self) -> Option<&ColorMap> {
+        self.display.colors()
+    }
+
+    // Region of the display touched since the last call, if any
+    pub fn take_display_changes(&mut self) -> Option<Rect> {
+        let mega = self.mega.as_mut().and_then(|mega| mega.take_dirty());
+        crate::display::merge(self.display.take_dirty(), mega)
     }
 
     pub fn end_frame(&mut self) {
         self.draw_occurred_this_frame = false;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.end_frame();
+        }
     }
 
     pub fn key_press(&mut self, key: u8) {
@@ -158,6 +607,25 @@ impl Cpu {
         self.keys[key as usize] = 0
     }
 
+    // The CHIP-8X second keypad, read by EXF2 and EXF5
+    pub fn second_key_press(&mut self, key: u8) {
+        self.second_keys[key as usize] = 1
+    }
+
+    pub fn second_key_release(&mut self, key: u8) {
+        self.second_keys[key as usize] = 0
+    }
+
+    // Last value FXF8 wrote to the CHIP-8X output port
+    pub fn output_port(&self) -> u8 {
+        self.output_port
+    }
+
+    // Latches a value for FXFB, which waits until there is one
+    pub fn send_input(&mut self, value: u8) {
+        self.input_port = Some(value);
+    }
+
     pub fn decrement_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
@@ -167,14 +635,31 @@ impl Cpu {
         }
     }
 
+    // In MegaChip mode this shows the frame that was drawn
     fn clear_screen(&mut self) {
-        self.display.clear();
+        match self.mega.as_mut().filter(|mega| mega.enabled()) {
+            Some(mega) => mega.present(),
+            None => self.display.clear(),
+        }
         self.pc += 2
     }
 
+    fn raise_fault(&mut self, message: String) {
+        eprintln!("{}", message);
+        self.fault = Some(message);
+    }
+
     fn return_from_subroutine(&mut self) {
+        if self.sp == 0 {
+            self.raise_fault(format!(
+                "Stack underflow: RET at {:#05x} with an empty stack",
+                self.pc
+            ));
+            return;
+        }
         self.sp -= 1;
         self.pc = self.stack[self.sp];
+        self.call_stack.ret();
     }
 
     fn jump(&mut self, address: u16) {
@@ -182,6 +667,14 @@ impl Cpu {
     }
 
     fn call_subroutine(&mut self, address: u16) {
+        if self.sp == self.stack.len() {
+            self.raise_fault(format!(
+                "Stack overflow: CALL at {:#05x} with a full stack",
+                self.pc
+            ));
+            return;
+        }
+        self.call_stack.call(self.pc, address);
         self.stack[self.sp] = self.pc + 2;
         self.sp += 1;
         self.pc = address;
@@ -228,19 +721,25 @@ impl Cpu {
 
     fn set_vx_to_vx_or_vy(&mut self, x: usize, y: usize) {
         self.v_registers[x] |= self.v_registers[y];
-        self.v_registers[0xF] = 0;
+        if self.quirks.logic {
+            self.v_registers[0xF] = 0;
+        }
         self.pc += 2
     }
 
     fn set_vx_to_vx_and_vy(&mut self, x: usize, y: usize) {
         self.v_registers[x] &= self.v_registers[y];
-        self.v_registers[0xF] = 0;
+        if self.quirks.logic {
+            self.v_registers[0xF] = 0;
+        }
         self.pc += 2
     }
 
     fn set_vx_to_vx_xor_vy(&mut self, x: usize, y: usize) {
         self.v_registers[x] ^= self.v_registers[y];
-        self.v_registers[0xF] = 0;
+        if self.quirks.logic {
+            self.v_registers[0xF] = 0;
+        }
         self.pc += 2
     }
 
@@ -269,9 +768,10 @@ impl Cpu {
     }
 
     fn shift_vx_right(&mut self, x: usize, y: usize) {
-        let vy = self.v_registers[y];
-        self.v_registers[x] = vy >> 1;
-        self.v_registers[0xF] = vy & 1;
+        let source = if self.quirks.shift { x } else { y };
+        let value = self.v_registers[source];
+        self.v_registers[x] = value >> 1;
+        self.v_registers[0xF] = value & 1;
 
         self.pc += 2
     }
@@ -289,9 +789,10 @@ impl Cpu {
     }
 
     fn shift_vx_left(&mut self, x: usize, y: usize) {
-        let vy = self.v_registers[y];
-        self.v_registers[x] = vy << 1;
-        self.v_registers[0xF] = vy >> 7;
+        let source = if self.quirks.shift { x } else { y };
+        let value = self.v_registers[source];
+        self.v_registers[x] = value << 1;
+        self.v_registers[0xF] = value >> 7;
 
         self.pc += 2
     }
@@ -303,12 +804,13 @@ impl Cpu {
     }
 
     fn set_i(&mut self, value: u16) {
-        self.i_register = value;
+        self.i_register = value as u32;
         self.pc += 2
     }
 
-    fn jump_to_v0_plus_nnn(&mut self, value: u16) {
-        self.pc = self.v_registers[0] as u16 + value;
+    fn jump_with_offset(&mut self, x: usize, value: u16) {
+        let register = if self.quirks.jump { x } else { 0 };
+        self.pc = self.v_registers[register] as u16 + value;
     }
 
     fn random(&mut self, x: usize, mask: u8) {
@@ -317,31 +819,51 @@ impl Cpu {
         self.pc += 2;
     }
 
-    fn draw_sprite(&mut self, x: usize, y: usize, height: u8) {
-        let x_pos = self.v_registers[x] as usize % 64;
-        let y_pos = self.v_registers[y] as usize % 32;
+    fn draw_sprite(&mut self, x: usize, y: usize, rows: u8) {
+        if let Some(mega) = self.mega.as_mut().filter(|mega| mega.enabled()) {
+            let (x, y) = (self.v_registers[x], self.v_registers[y]);
+            let i = self.i_register as usize;
+            let collision = mega.draw_sprite(&self.memory, i, x as usize, y as usize);
+            self.v_registers[0xF] = collision as u8;
+            self.draw_occurred_this_frame = true;
+            self.pc += 2;
+            return;
+        }
+        // SCHIP lores pixels are 2x2 blocks on the 128x64 screen
+        let scale = if self.platform.is_schip() && !self.hires {
+            2
+        } else {
+            1
+        };
+        let width = self.display.width() / scale;
+        let height = self.display.height() / scale;
+        let x_pos = self.v_registers[x] as usize % width;
+        let y_pos = self.v_registers[y] as usize % height;
 
+        let (rows, bytes_per_row) = self.sprite_shape(rows);
+        let i = self.i_register as usize;
         let mut collision = false;
+        self.cover(i, rows * bytes_per_row, coverage::SPRITE);
 
-        for row in 0..height {
-            let sprite = self.memory[(self.i_register + row as u16) as usize];
-            let y = y_pos + row as usize;
-            if y >= 32 {
+        for row in 0..rows {
+            let address = i + row * bytes_per_row;
+            let mut sprite = (self.memory[address] as u32) << 24;
+            if bytes_per_row == 2 {
+                sprite |= (self.memory[address + 1] as u32) << 16;
+            }
+            let y = y_pos + row;
+            if y >= height && self.quirks.clip {
                 break;
             }
-            for col in 0..8 {
-                let x = x_pos + col;
-                if x >= 64 {
-                    break;
-                }
-                if (sprite & (0x80 >> col)) == 0 {
-                    continue;
-                }
-
-                let x_coord = x_pos + col;
-                let y_coord = y_pos + row as usize;
-
-                if self.display.set_pixel(x_coord, y_coord) {
+            if scale == 2 {
+                sprite = double_pixels(sprite);
+            }
+            for line in 0..scale {
+                let display_y = (y % height) * scale + line;
+                if self
+                    .display
+                    .draw_row(x_pos * scale, display_y, sprite, self.quirks.clip)
+                {
                     collision = true;
                 }
             }
@@ -352,6 +874,16 @@ impl Cpu {
         self.pc += 2
     }
 
+    // Rows and bytes per row of a sprite. DXY0 draws a 16x16 sprite on SCHIP,
+    // except in SCHIP 1.0's lores mode, where it draws 8x16.
+    fn sprite_shape(&self, rows: u8) -> (usize, usize) {
+        match self.platform {
+            Platform::Schip10 if rows == 0 && !self.hires => (16, 1),
+            platform if platform.is_schip() && rows == 0 => (16, 2),
+            _ => (rows as usize, 1),
+        }
+    }
+
     fn skip_if_key_pressed(&mut self, register: usize) {
         let key = self.v_registers[register] as usize;
         self.pc += if self.keys[key] == 1 { 4 } else { 2 };
@@ -384,6 +916,127 @@ impl Cpu {
         }
     }
 
+    fn cycle_background(&mut self) {
+        self.display.cycle_background();
+        self.pc += 2
+    }
+
+    // Each nibble is added on its own, dropping the carry out of it
+    fn add_nibbles(&mut self, x: usize, y: usize) {
+        let vx = self.v_registers[x];
+        let vy = self.v_registers[y];
+        let high = (vx & 0xF0).wrapping_add(vy & 0xF0);
+        let low = (vx & 0x0F) + (vy & 0x0F);
+        self.v_registers[x] = high | (low & 0x0F);
+        self.pc += 2
+    }
+
+    // VX holds the horizontal position and VX+1 the vertical one. BXY0 colors
+    // whole zones four rows high, with each position's high nibble giving the
+    // extra zones to cover; BXYN colors the zone under an 8xN sprite.
+    fn set_zone_color(&mut self, x: usize, y: usize, height: u8) {
+        let horizontal = self.v_registers[x] as usize;
+        let vertical = self.v_registers[(x + 1) % 16] as usize;
+        let color = self.v_registers[y];
+        if height == 0 {
+            let columns = (horizontal >> 4) + 1;
+            let rows = ((vertical >> 4) + 1) * 4;
+            let position = (horizontal & 0xF, (vertical & 0xF) * 4);
+            self.display
+                .color_zones(position.0, position.1, (columns, rows), color);
+        } else {
+            let column = (horizontal % WIDTH) / ZONE_WIDTH;
+            self.display
+                .color_zones(column, vertical, (1, height as usize), color);
+        }
+        self.pc += 2
+    }
+
+    fn skip_if_second_key_pressed(&mut self, register: usize) {
+        let key = self.v_registers[register] as usize & 0xF;
+        self.pc += if self.second_keys[key] == 1 { 4 } else { 2 };
+    }
+
+    fn skip_if_second_key_not_pressed(&mut self, register: usize) {
+        let key = self.v_registers[register] as usize & 0xF;
+        self.pc += if self.second_keys[key] == 0 { 4 } else { 2 };
+    }
+
+    fn output(&mut self, register: usize) {
+        self.output_port = self.v_registers[register];
+        self.pc += 2
+    }
+
+    fn wait_for_input(&mut self, register: usize) {
+        if let Some(value) = self.input_port.take() {
+            self.v_registers[register] = value;
+            self.pc += 2
+        }
+    }
+
+    fn set_mega_mode(&mut self, enabled: bool) {
+        self.mega_op(|mega, _| mega.set_enabled(enabled));
+        self.display.clear();
+    }
+
+    // Runs a MegaChip instruction against the MegaChip screen, which is
+    // there whether or not MegaChip mode is on
+    fn mega_op(&mut self, op: impl FnOnce(&mut MegaScreen, &[u8])) {
+        if let Some(mega) = &mut self.mega {
+            op(mega, &self.memory);
+        }
+        self.pc += 2
+    }
+
+    // 01NN NNNN sets all 24 bits of I from two words
+    fn set_i_long(&mut self, high: u8) {
+        let address = self.pc as usize + 2;
+        let low = (self.memory[address] as u32) << 8 | self.memory[address + 1] as u32;
+        self.i_register = (high as u32) << 16 | low;
+        self.pc += 4
+    }
+
+    // 0600 loops the sound and 0601 plays it once. Playback is left for
+    // later, so this only records which sample it would be.
+    fn select_sample(&mut self, mode: u8) {
+        let i = self.i_register as usize;
+        self.sample = Some(read_sample(&self.memory, i, mode == 0));
+        self.pc += 2
+    }
+
+    // SCHIP 1.1 scrolls by 128x64 pixels in either mode, so lores programs
+    // move by half a pixel at a time
+    fn scroll(&mut self, op: impl FnOnce(&mut Display)) {
+        op(&mut self.display);
+        self.draw_occurred_this_frame = true;
+        self.pc += 2
+    }
+
+    // The screen is left as it is, and is redrawn at the new pixel size
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pc += 2
+    }
+
+    fn set_i_to_big_sprite(&mut self, register: usize) {
+        let digit = (self.v_registers[register] % 10) as usize;
+        self.i_register = (BIG_FONT_START + digit * 10) as u32;
+        self.pc += 2
+    }
+
+    // Only V0-V7 have a flag to go to
+    fn store_rpl_flags(&mut self, x: usize) {
+        let count = x.min(7) + 1;
+        self.rpl_flags[..count].copy_from_slice(&self.v_registers[..count]);
+        self.pc += 2
+    }
+
+    fn load_rpl_flags(&mut self, x: usize) {
+        let count = x.min(7) + 1;
+        self.v_registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+        self.pc += 2
+    }
+
     fn set_delay_timer(&mut self, register: usize) {
         let value = self.v_registers[register];
         self.delay_timer = value;
@@ -398,13 +1051,13 @@ impl Cpu {
 
     fn add_vx_to_i(&mut self, x: usize) {
         let vx = self.v_registers[x];
-        self.i_register += vx as u16;
+        self.i_register = (self.i_register + vx as u32) & I_MASK;
         self.pc += 2
     }
 
     fn set_i_to_sprite(&mut self, register: usize) {
         let value = self.v_registers[register];
-        self.i_register = (value * 5) as u16;
+        self.i_register = (value * 5) as u32;
         self.pc += 2
     }
 
@@ -416,6 +1069,8 @@ impl Cpu {
         let ones = value % 10;
 
         let i = self.i_register as usize;
+        self.cover(i, 3, coverage::WRITTEN);
+        self.invalidate(i, 3);
         self.memory[i] = hundreds;
         self.memory[i + 1] = tens;
         self.memory[i + 2] = ones;
@@ -425,25 +1080,276 @@ impl Cpu {
 
     fn store_registers(&mut self, x: usize) {
         let i = self.i_register as usize;
+        self.cover(i, x + 1, coverage::WRITTEN);
+        self.invalidate(i, x + 1);
 
         for idx in 0..=x {
             self.memory[i + idx] = self.v_registers[idx];
         }
 
-        self.i_register += (x as u16) + 1;
+        self.i_register += self.load_store_increment(x);
 
         self.pc += 2
     }
 
+    // CHIP-48 and SCHIP 1.0 leave I one short of the end of the range;
+    // SCHIP 1.1 and MegaChip leave it alone. There the version decides, so
+    // the load/store quirk only applies to the other platforms.
+    fn load_store_increment(&self, x: usize) -> u32 {
+        match self.platform {
+            Platform::Schip10 => x as u32,
+            Platform::Schip11 | Platform::MegaChip => 0,
+            _ if self.quirks.load_store => 0,
+            _ => x as u32 + 1,
+        }
+    }
+
     fn load_registers(&mut self, x: usize) {
         let i = self.i_register as usize;
+        self.cover(i, x + 1, coverage::READ);
 
         for idx in 0..=x {
             self.v_registers[idx] = self.memory[i + idx];
         }
 
-        self.i_register += (x as u16) + 1;
+        self.i_register += self.load_store_increment(x);
 
         self.pc += 2
     }
 }
+
+// Doubles each of the top 16 pixels of a sprite row, for drawing lores
+// sprites in 2x2 blocks
+fn double_pixels(sprite: u32) -> u32 {
+    (0..16)
+        .filter(|bit| sprite & (0x8000_0000 >> bit) != 0)
+        .fold(0, |doubled, bit| doubled | (0xC000_0000 >> (bit * 2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::DEFAULT_FOREGROUND;
+
+    fn load_words(platform: Platform, program: &[u16]) -> Cpu {
+        let mut cpu = build_cpu();
+        cpu.set_platform(platform);
+        cpu.load_font_data();
+        let bytes: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        cpu.load_program(&bytes);
+        cpu
+    }
+
+    // Loads a program for a platform and runs one instruction per word
+    fn run_program(platform: Platform, program: &[u16]) -> Cpu {
+        let mut cpu = load_words(platform, program);
+        for _ in program {
+            cpu.cycle();
+        }
+        cpu
+    }
+
+    #[test]
+    fn add_nibbles_drops_the_carries() {
+        // 0x9A + 0x78 nibble by nibble is 0x(9+7)(A+8), or 0x02
+        let cpu = run_program(Platform::Chip8X, &[0x609A, 0x6178, 0x5011]);
+        assert_eq!(cpu.registers().v[0], 0x02);
+        assert_eq!(cpu.registers().v[1], 0x78);
+    }
+
+    #[test]
+    fn cycle_background_wraps_after_four_colors() {
+        let mut cpu = run_program(Platform::Chip8X, &[]);
+        cpu.load_program(&[0x02, 0xA0].repeat(4));
+        for background in [1, 2, 3, 0] {
+            cpu.take_display_changes();
+            cpu.cycle();
+            assert_eq!(cpu.colors().unwrap().background(), background);
+            assert!(cpu.take_display_changes().is_some());
+        }
+    }
+
+    #[test]
+    fn zone_colors_cover_whole_zones_or_a_sprite() {
+        // BXY0: two zone columns from column 2, four rows from row 4
+        let cpu = run_program(Platform::Chip8X, &[0x6212, 0x6301, 0x6405, 0xB240]);
+        let colors = cpu.colors().unwrap();
+        assert_eq!(colors.foreground(16, 4), 5);
+        assert_eq!(colors.foreground(31, 7), 5);
+        assert_eq!(colors.foreground(32, 4), DEFAULT_FOREGROUND);
+        assert_eq!(colors.foreground(16, 8), DEFAULT_FOREGROUND);
+        assert_eq!(colors.foreground(16, 3), DEFAULT_FOREGROUND);
+
+        // BXYN: the zone under an 8x3 sprite at (33, 10)
+        let cpu = run_program(Platform::Chip8X, &[0x6221, 0x630A, 0x6405, 0xB243]);
+        let colors = cpu.colors().unwrap();
+        assert_eq!(colors.foreground(32, 10), 5);
+        assert_eq!(colors.foreground(39, 12), 5);
+        assert_eq!(colors.foreground(32, 13), DEFAULT_FOREGROUND);
+        assert_eq!(colors.foreground(40, 10), DEFAULT_FOREGROUND);
+        assert_eq!(colors.foreground(32, 9), DEFAULT_FOREGROUND);
+    }
+
+    #[test]
+    fn input_port_waits_for_a_value() {
+        let mut cpu = run_program(Platform::Chip8X, &[0x6042, 0xF0F8, 0xF1FB]);
+        assert_eq!(cpu.output_port(), 0x42);
+        assert_eq!(cpu.pc(), 0x304);
+        cpu.cycle();
+        assert_eq!(cpu.pc(), 0x304);
+        cpu.send_input(0x17);
+        cpu.cycle();
+        assert_eq!(cpu.registers().v[1], 0x17);
+        assert_eq!(cpu.pc(), 0x306);
+    }
+
+    #[test]
+    fn long_i_takes_the_following_word() {
+        let mut cpu = load_words(Platform::MegaChip, &[0x0112, 0x3456, 0x6001]);
+        cpu.cycle();
+        // The second word is an operand, so the third runs next
+        assert_eq!(cpu.i_register(), 0x123456);
+        assert_eq!(cpu.pc(), 0x204);
+        cpu.cycle();
+        assert_eq!(cpu.registers().v[0], 1);
+    }
+
+    #[test]
+    fn megachip_runs_schip_opcodes_outside_megachip_mode() {
+        // HIGH, then LOW and a scroll down on the 128x64 screen
+        let cpu = run_program(Platform::MegaChip, &[0x00FF, 0x00FE, 0x00C1]);
+        assert_eq!(cpu.fault(), None);
+        assert_eq!(cpu.display_size(), (128, 64));
+        assert_eq!(cpu.pc(), 0x206);
+    }
+
+    fn lit_pixels(cpu: &Cpu) -> usize {
+        cpu.get_display()
+            .pixels
+            .iter()
+            .filter(|&&pixel| pixel != 0)
+            .count()
+    }
+
+    #[test]
+    fn only_schip_1_1_scrolls_and_by_half_a_lores_pixel() {
+        // A single lores pixel at (0, 0), then scroll down one row
+        let program = [0xA20A, 0xD011, 0x00C1, 0x1206, 0x0000, 0x8000];
+        let mut cpu = load_words(Platform::Schip11, &program);
+        for _ in 0..3 {
+            cpu.cycle();
+        }
+        let display = cpu.get_display();
+        assert_eq!(display.pixel(0, 0), 0);
+        assert_eq!(display.pixel(0, 1), 1);
+        assert_eq!(display.pixel(1, 2), 1);
+        assert_eq!(display.pixel(0, 3), 0);
+
+        let mut cpu = load_words(Platform::Schip10, &program);
+        for _ in 0..3 {
+            cpu.cycle();
+        }
+        assert!(cpu.fault().is_some());
+        assert_eq!(cpu.get_display().pixel(0, 0), 1);
+    }
+
+    #[test]
+    fn lores_big_sprites_are_8x16_on_schip_1_0() {
+        let mut program = vec![0xA206, 0xD000, 0x1204];
+        program.extend([0xFFFF; 16]);
+        for (platform, pixels) in [(Platform::Schip10, 8 * 16), (Platform::Schip11, 16 * 16)] {
+            let mut cpu = load_words(platform, &program);
+            cpu.cycle();
+            cpu.cycle();
+            // Each lores pixel covers four on the 128x64 screen
+            assert_eq!(lit_pixels(&cpu), pixels * 4, "{}", platform.name());
+        }
+    }
+
+    #[test]
+    fn load_and_store_move_i_by_platform() {
+        let program = [0xA300, 0xF255, 0xF265];
+        for (platform, i) in [
+            (Platform::Chip8, 0x306),
+            (Platform::Schip10, 0x304),
+            (Platform::Schip11, 0x300),
+            (Platform::MegaChip, 0x300),
+        ] {
+            let cpu = run_program(platform, &program);
+            assert_eq!(cpu.i_register(), i, "{}", platform.name());
+        }
+
+        // The quirk only applies off SCHIP
+        let mut quirks = build_quirks();
+        quirks.load_store = true;
+        for (platform, i) in [(Platform::Chip8, 0x300), (Platform::Schip10, 0x304)] {
+            let mut cpu = load_words(platform, &program);
+            cpu.set_quirks(quirks);
+            for _ in program {
+                cpu.cycle();
+            }
+            assert_eq!(cpu.i_register(), i, "{}", platform.name());
+        }
+    }
+
+    #[test]
+    fn exit_halts_the_program() {
+        let mut cpu = load_words(Platform::Schip11, &[0x6001, 0x00FD, 0x6002]);
+        cpu.set_block_cache(true);
+        assert_eq!(cpu.run(10), 2);
+        assert!(cpu.halted());
+        assert_eq!(cpu.fault(), None);
+        assert_eq!(cpu.pc(), 0x202);
+        cpu.cycle();
+        assert_eq!(cpu.run(10), 0);
+        assert_eq!(cpu.registers().v[0], 1);
+    }
+
+    // A skip at the last word of memory, with V0 still zero
+    fn skip_from_the_top_of_4k(platform: Platform) -> Cpu {
+        let mut cpu = load_words(platform, &[]);
+        cpu.write_memory(0xFFE, 0x30);
+        cpu.write_memory(0xFFF, 0x00);
+        cpu.set_registers(Registers {
+            pc: 0xFFE,
+            ..cpu.registers()
+        });
+        cpu.cycle();
+        cpu
+    }
+
+    #[test]
+    fn running_off_the_end_of_memory_faults() {
+        let mut cpu = skip_from_the_top_of_4k(Platform::Chip8);
+        assert_eq!(cpu.pc(), 0x1002);
+        cpu.cycle();
+        assert_eq!(cpu.fault(), Some("PC 0x1002 is outside memory"));
+        assert_eq!(cpu.pc(), 0x1002);
+    }
+
+    #[test]
+    fn megachip_runs_code_above_4k() {
+        let mut cpu = skip_from_the_top_of_4k(Platform::MegaChip);
+        cpu.write_memory(0x1002, 0x60);
+        cpu.write_memory(0x1003, 0x05);
+        cpu.cycle();
+        assert_eq!(cpu.fault(), None);
+        assert_eq!(cpu.registers().v[0], 5);
+        assert_eq!(cpu.pc(), 0x1004);
+    }
+
+    #[test]
+    fn cached_blocks_fault_past_the_end_of_memory() {
+        let mut cpu = skip_from_the_top_of_4k(Platform::Chip8);
+        cpu.set_block_cache(true);
+        assert_eq!(cpu.run(3), 3);
+        assert_eq!(cpu.fault(), Some("PC 0x1002 is outside memory"));
+
+        let mut cpu = skip_from_the_top_of_4k(Platform::MegaChip);
+        cpu.write_memory(0x1002, 0x60);
+        cpu.write_memory(0x1003, 0x05);
+        cpu.set_block_cache(true);
+        cpu.run(1);
+        assert_eq!(cpu.registers().v[0], 5);
+    }
+}
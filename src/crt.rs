@@ -0,0 +1,232 @@
+pub const EFFECT_SCALE: usize = 6;
+
+const SCANLINE_SHADE: f32 = 0.65;
+const GRID_SHADE: f32 = 0.8;
+const BLOOM_STRENGTH: f32 = 0.45;
+const CURVATURE: f32 = 0.08;
+const VIGNETTE_STRENGTH: f32 = 0.35;
+
+#[derive(Clone, Copy, Default)]
+pub struct Effects {
+    pub scanlines: bool,
+    pub grid: bool,
+    pub bloom: bool,
+    pub curvature: bool,
+    pub vignette: bool,
+}
+
+pub fn parse_effects(text: &str) -> Result<Effects, String> {
+    let mut effects = Effects::default();
+    for name in text.split(',') {
+        match name.trim() {
+            "none" => {}
+            "scanlines" => effects.scanlines = true,
+            "grid" => effects.grid = true,
+            "bloom" => effects.bloom = true,
+            "curvature" => effects.curvature = true,
+            "vignette" => effects.vignette = true,
+            "all" => {
+                effects = Effects {
+                    scanlines: true,
+                    grid: true,
+                    bloom: true,
+                    curvature: true,
+                    vignette: true,
+                }
+            }
+            other => return Err(format!("Unknown CRT effect '{}'", other)),
+        }
+    }
+    Ok(effects)
+}
+
+impl Effects {
+    pub fn any(&self) -> bool {
+        self.scanlines || self.grid || self.bloom || self.curvature || self.vignette
+    }
+}
+
+// Upscales an RGBA frame by EFFECT_SCALE and applies the enabled effects in
+// software, so the output is identical with or without a GPU.
+pub fn apply(effects: &Effects, source: &[u8], width: usize, height: usize, out: &mut [u8]) {
+    let scale = EFFECT_SCALE;
+    let (out_width, out_height) = (width * scale, height * scale);
+
+    let mut image: Vec<[f32; 3]> = Vec::with_capacity(out_width * out_height);
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let i = ((y / scale) * width + x / scale) * 4;
+            image.push([source[i] as f32, source[i + 1] as f32, source[i + 2] as f32]);
+        }
+    }
+
+    let glow = if effects.bloom {
+        Some(blur(&image, out_width, out_height, scale))
+    } else {
+        None
+    };
+
+    for (i, pixel) in image.iter_mut().enumerate() {
+        let (x, y) = (i % out_width, i / out_width);
+        let mut shade = 1.0;
+        if effects.grid && (x % scale == scale - 1 || y % scale == scale - 1) {
+            shade *= GRID_SHADE;
+        }
+        if effects.scanlines && y % 2 == 1 {
+            shade *= SCANLINE_SHADE;
+        }
+        for channel in pixel.iter_mut() {
+            *channel *= shade;
+        }
+        if let Some(glow) = &glow {
+            for (channel, bright) in pixel.iter_mut().zip(glow[i]) {
+                *channel += bright * BLOOM_STRENGTH;
+            }
+        }
+    }
+
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let u = (x as f32 + 0.5) / out_width as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / out_height as f32 * 2.0 - 1.0;
+
+            let (su, sv) = if effects.curvature {
+                (u * (1.0 + CURVATURE * v * v), v * (1.0 + CURVATURE * u * u))
+            } else {
+                (u, v)
+            };
+
+            let mut color = if su.abs() > 1.0 || sv.abs() > 1.0 {
+                [0.0; 3]
+            } else {
+                let sx = (((su + 1.0) / 2.0 * out_width as f32) as usize).min(out_width - 1);
+                let sy = (((sv + 1.0) / 2.0 * out_height as f32) as usize).min(out_height - 1);
+                image[sy * out_width + sx]
+            };
+
+            if effects.vignette {
+                let falloff = (1.0 - VIGNETTE_STRENGTH * (u * u + v * v) / 2.0).max(0.0);
+                for channel in color.iter_mut() {
+                    *channel *= falloff;
+                }
+            }
+
+            let o = (y * out_width + x) * 4;
+            for channel in 0..3 {
+                out[o + channel] = color[channel].round().clamp(0.0, 255.0) as u8;
+            }
+            out[o + 3] = 255;
+        }
+    }
+}
+
+// Separable box blur, horizontal then vertical
+fn blur(image: &[[f32; 3]], width: usize, height: usize, radius: usize) -> Vec<[f32; 3]> {
+    let pass = |input: &[[f32; 3]], horizontal: bool| {
+        let mut output = vec![[0.0; 3]; input.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 3];
+                let mut count = 0.0;
+                for offset in -(radius as isize)..=radius as isize {
+                    let (sx, sy) = if horizontal {
+                        (x as isize + offset, y as isize)
+                    } else {
+                        (x as isize, y as isize + offset)
+                    };
+                    if sx < 0 || sy < 0 || sx >= width as isize || sy >= height as isize {
+                        continue;
+                    }
+                    let sample = input[sy as usize * width + sx as usize];
+                    for channel in 0..3 {
+                        sum[channel] += sample[channel];
+                    }
+                    count += 1.0;
+                }
+                output[y * width + x] = sum.map(|c| c / count);
+            }
+        }
+        output
+    };
+    pass(&pass(image, true), false)
+}
+
+// CI has no GPU, so each effect is rendered on a small fixed frame and
+// compared pixel for pixel with a reference PNG in tests/golden/crt. On a
+// mismatch the actual image is written to target/crt-golden; check it by eye
+// and copy it over the reference if the change is intended.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::save_png;
+    use crate::display::build_snapshot;
+    use crate::palette::builtin_palettes;
+    use crate::phosphor::{Persistence, build_phosphor, render_display};
+    use std::fs::File;
+    use std::io::BufReader;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 4;
+
+    fn source() -> Vec<u8> {
+        let mut display = build_snapshot(WIDTH, HEIGHT);
+        for (index, pixel) in display.pixels.iter_mut().enumerate() {
+            *pixel = ((index * 7) % 3 == 0) as u8;
+        }
+        let mut phosphor = build_phosphor(Persistence::Off);
+        phosphor.update(&display, None);
+        let mut frame = vec![0; WIDTH * HEIGHT * 4];
+        render_display(&phosphor, &mut frame, &builtin_palettes()[0], None);
+        frame
+    }
+
+    fn render(effects: &str) -> Vec<u8> {
+        let effects = parse_effects(effects).unwrap();
+        let mut out = vec![0; WIDTH * HEIGHT * 4 * EFFECT_SCALE * EFFECT_SCALE];
+        apply(&effects, &source(), WIDTH, HEIGHT, &mut out);
+        out
+    }
+
+    fn load_png(location: &str) -> Option<(Vec<u8>, (usize, usize))> {
+        let file = File::open(location).ok()?;
+        let mut reader = png::Decoder::new(BufReader::new(file)).read_info().ok()?;
+        let mut image = vec![0; reader.output_buffer_size()?];
+        let info = reader.next_frame(&mut image).ok()?;
+        if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+            return None;
+        }
+        image.truncate(info.buffer_size());
+        Some((image, (info.width as usize, info.height as usize)))
+    }
+
+    #[test]
+    fn effects_match_their_golden_images() {
+        let root = env!("CARGO_MANIFEST_DIR");
+        let size = (WIDTH * EFFECT_SCALE, HEIGHT * EFFECT_SCALE);
+        let mut mismatches = Vec::new();
+        for effects in [
+            "none",
+            "scanlines",
+            "grid",
+            "bloom",
+            "curvature",
+            "vignette",
+            "all",
+        ] {
+            let actual = render(effects);
+            let golden = format!("{}/tests/golden/crt/{}.png", root, effects);
+            if load_png(&golden) == Some((actual.clone(), size)) {
+                continue;
+            }
+            let directory = format!("{}/target/crt-golden", root);
+            std::fs::create_dir_all(&directory).unwrap();
+            let location = format!("{}/{}.png", directory, effects);
+            save_png(&location, &actual, size, 1).unwrap();
+            mismatches.push(format!(
+                "{} differs from {}, see {}",
+                effects, golden, location
+            ));
+        }
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+}
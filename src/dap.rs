@@ -0,0 +1,1040 @@
+use std::collections::HashSet;
+use std::fmt::UpperHex;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, TryRecvError, channel};
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+use crate::assembler::parse_number;
+use crate::cpu::{Cpu, build_cpu};
+use crate::disassembler::disassemble;
+use crate::loader::load_program;
+use crate::platform::parse_platform;
+use crate::symbols::{Symbols, load_symbols};
+
+const FRAME_TIME: Duration = Duration::from_nanos(16_666_667);
+const CYCLES_PER_FRAME: usize = 12;
+const THREAD_ID: i64 = 1;
+const REGISTERS_REFERENCE: i64 = 1;
+const TIMERS_REFERENCE: i64 = 2;
+const STACK_REFERENCE: i64 = 3;
+// Limits on what one request can ask for, whatever the memory size
+const MAX_READ: i64 = 0x10000;
+const MAX_DISASSEMBLY: i64 = 4096;
+const MAX_BODY_LENGTH: usize = 1 << 20;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+enum Resume {
+    Continue,
+    StepOver { pc: u16, depth: usize },
+    StepOut { depth: usize },
+}
+
+struct Debugger {
+    cpu: Cpu,
+    symbols: Option<Symbols>,
+    source_breakpoints: HashSet<u16>,
+    function_breakpoints: HashSet<u16>,
+    instruction_breakpoints: HashSet<u16>,
+    running: Option<Resume>,
+    stop_on_entry: bool,
+    cycles_per_frame: usize,
+    cycle_count: usize,
+    frame_count: u64,
+    frame_start: Instant,
+    seq: i64,
+    events: Vec<Value>,
+    output: Box<dyn Write>,
+}
+
+// Speaks the Debug Adapter Protocol on stdin/stdout so editors such as
+// VS Code can launch and debug ROMs. Returns the process exit code.
+pub fn dap_command() -> i32 {
+    run_session(spawn_reader(), Box::new(std::io::stdout()))
+}
+
+// Handles requests until the client disconnects, writing responses and
+// events to the output
+fn run_session(messages: Receiver<Value>, output: Box<dyn Write>) -> i32 {
+    let mut debugger = Debugger {
+        cpu: build_cpu(),
+        symbols: None,
+        source_breakpoints: HashSet::new(),
+        function_breakpoints: HashSet::new(),
+        instruction_breakpoints: HashSet::new(),
+        running: None,
+        stop_on_entry: false,
+        cycles_per_frame: CYCLES_PER_FRAME,
+        cycle_count: 0,
+        frame_count: 0,
+        frame_start: Instant::now(),
+        seq: 0,
+        events: Vec::new(),
+        output,
+    };
+
+    loop {
+        let message = if debugger.running.is_some() {
+            match messages.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return 0,
+            }
+        } else {
+            match messages.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return 0,
+            }
+        };
+
+        match message {
+            Some(message) => {
+                let keep_going = debugger.handle(&message);
+                debugger.flush_events();
+                if !keep_going {
+                    return 0;
+                }
+            }
+            None => {
+                debugger.run_frame();
+                debugger.flush_events();
+            }
+        }
+    }
+}
+
+fn spawn_reader() -> Receiver<Value> {
+    let (sender, messages) = channel();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(std::io::stdin().lock());
+        while let Some(body) = read_body(&mut reader) {
+            match serde_json::from_slice(&body) {
+                Ok(message) => {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("Ignoring malformed DAP message: {}", e),
+            }
+        }
+    });
+    messages
+}
+
+// Bodies over MAX_BODY_LENGTH are skipped rather than read into memory
+fn read_body(reader: &mut impl BufRead) -> Option<Vec<u8>> {
+    loop {
+        let length = read_headers(reader)?;
+        if length > MAX_BODY_LENGTH {
+            eprintln!("Ignoring DAP message of {} bytes", length);
+            std::io::copy(
+                &mut reader.by_ref().take(length as u64),
+                &mut std::io::sink(),
+            )
+            .ok()?;
+            continue;
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).ok()?;
+        return Some(body);
+    }
+}
+
+// Returns the Content-Length of the next message
+fn read_headers(reader: &mut impl BufRead) -> Option<usize> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                return length;
+            }
+            continue;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+}
+
+impl Debugger {
+    // Returns false once the client disconnects
+    fn handle(&mut self, request: &Value) -> bool {
+        let command = request["command"].as_str().unwrap_or("");
+        let arguments = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSetVariable": true,
+                "supportsEvaluateForHovers": true,
+                "supportsReadMemoryRequest": true,
+                "supportsWriteMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setFunctionBreakpoints" => Ok(self.set_function_breakpoints(arguments)),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stop("entry", None);
+                } else {
+                    self.running = Some(Resume::Continue);
+                }
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Timers", "variablesReference": TIMERS_REFERENCE, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REFERENCE, "expensive": false },
+            ] })),
+            "variables" => Ok(self.variables(arguments["variablesReference"].as_i64())),
+            "setVariable" => self.set_variable(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => {
+                self.resume(Resume::Continue);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => {
+                let pc = self.cpu.pc();
+                let opcode = self.cpu.fetch_instruction();
+                if opcode & 0xF000 == 0x2000 {
+                    let depth = self.cpu.stack().len();
+                    self.resume(Resume::StepOver { pc: pc + 2, depth });
+                } else {
+                    self.step_instruction();
+                }
+                Ok(json!({}))
+            }
+            "stepIn" => {
+                self.step_instruction();
+                Ok(json!({}))
+            }
+            "stepOut" => {
+                let depth = self.cpu.stack().len();
+                if depth == 0 {
+                    self.step_instruction();
+                } else {
+                    self.resume(Resume::StepOut { depth });
+                }
+                Ok(json!({}))
+            }
+            "pause" => {
+                self.stop("pause", None);
+                Ok(json!({}))
+            }
+            "readMemory" => self.read_memory(arguments),
+            "writeMemory" => self.write_memory(arguments),
+            "disassemble" => self.disassemble(arguments),
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(json!({})));
+                return false;
+            }
+            _ => Err(format!("Unsupported request '{}'", command)),
+        };
+        self.respond(request, result);
+        true
+    }
+
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        let output = &mut self.output;
+        let result = write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+            .and_then(|_| output.flush());
+        if let Err(e) = result {
+            eprintln!("Failed to write DAP message: {}", e);
+        }
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn event(&mut self, name: &str, body: Value) {
+        self.events
+            .push(json!({ "type": "event", "event": name, "body": body }));
+    }
+
+    fn flush_events(&mut self) {
+        for event in std::mem::take(&mut self.events) {
+            self.send(event);
+        }
+    }
+
+    // The initialized event is only sent once the program is loaded, so
+    // source breakpoints can be resolved against its symbols
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"]
+            .as_str()
+            .ok_or("Launch configuration needs a 'program' path")?;
+
+        self.cpu = build_cpu();
+        if let Some(name) = arguments["platform"].as_str() {
+            self.cpu.set_platform(parse_platform(name)?);
+        }
+        self.cycles_per_frame = CYCLES_PER_FRAME;
+        let loaded = load_program(&mut self.cpu, program)?;
+        if let Some(options) = loaded.options {
+            self.cpu.set_quirks(options.quirks);
+            if let Some(tickrate) = options.tickrate {
+                self.cycles_per_frame = tickrate.max(1);
+            }
+        }
+        self.symbols = match arguments["symbols"].as_str() {
+            Some(location) => Some(load_symbols(location)?),
+            None => loaded.symbols,
+        };
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.event("initialized", json!({}));
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["source"]["path"].as_str().unwrap_or("");
+        let lines: Vec<usize> = arguments["breakpoints"]
+            .as_array()
+            .map(|breakpoints| {
+                breakpoints
+                    .iter()
+                    .filter_map(|b| b["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let symbols = self.symbols.as_ref().filter(|symbols| {
+            symbols
+                .source()
+                .is_some_and(|source| same_file(source, path))
+        });
+
+        self.source_breakpoints.clear();
+        let mut results = Vec::new();
+        for line in lines {
+            match symbols.and_then(|symbols| symbols.address_of_line(line)) {
+                Some((address, actual)) => {
+                    self.source_breakpoints.insert(address);
+                    results.push(json!({
+                        "verified": true,
+                        "line": actual,
+                        "instructionReference": format_address(address),
+                    }));
+                }
+                None => results.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No code at this line",
+                })),
+            }
+        }
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    // Function breakpoints name a label, optionally with an offset
+    fn set_function_breakpoints(&mut self, arguments: &Value) -> Value {
+        self.function_breakpoints.clear();
+        let mut results = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let name = breakpoint["name"].as_str().unwrap_or("");
+            match self
+                .symbols
+                .as_ref()
+                .and_then(|symbols| symbols.resolve(name))
+            {
+                Some(address) => {
+                    self.function_breakpoints.insert(address);
+                    results.push(json!({
+                        "verified": true,
+                        "instructionReference": format_address(address),
+                    }));
+                }
+                None => results.push(json!({
+                    "verified": false,
+                    "message": format!("Unknown symbol '{}'", name),
+                })),
+            }
+        }
+        json!({ "breakpoints": results })
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        self.instruction_breakpoints.clear();
+        let mut results = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let address = breakpoint["instructionReference"]
+                .as_str()
+                .and_then(parse_reference)
+                .map(|address| address + breakpoint["offset"].as_i64().unwrap_or(0));
+            match address.filter(|address| (0..0x1000).contains(address)) {
+                Some(address) => {
+                    self.instruction_breakpoints.insert(address as u16);
+                    results.push(json!({
+                        "verified": true,
+                        "instructionReference": format_address(address as u16),
+                    }));
+                }
+                None => results.push(json!({
+                    "verified": false,
+                    "message": "Invalid address",
+                })),
+            }
+        }
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn stack_frame(&self, id: usize, address: u16, function: Option<u16>) -> Value {
+        let name = match function {
+            Some(entry) => self.function_name(entry),
+            None => self
+                .symbols
+                .as_ref()
+                .and_then(|symbols| symbols.name(address))
+                .unwrap_or_else(|| format_address(address)),
+        };
+        let mut frame = json!({
+            "id": id,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format_address(address),
+        });
+        if let Some(symbols) = &self.symbols
+            && let (Some(source), Some(line)) = (symbols.source(), symbols.line(address))
+        {
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+            frame["source"] = json!({
+                "name": Path::new(source).file_name().map(|n| n.to_string_lossy()),
+                "path": source,
+            });
+        }
+        frame
+    }
+
+    // The innermost frame is at PC and named after the subroutine the last
+    // CALL entered; each outer frame is shown at its call site
+    fn stack_trace(&self) -> Value {
+        let call_stack = self.cpu.call_stack();
+        let function = |depth: usize| {
+            depth
+                .checked_sub(1)
+                .and_then(|index| call_stack[index].entry)
+        };
+        let mut frames = vec![self.stack_frame(0, self.cpu.pc(), function(call_stack.len()))];
+        for (depth, frame) in call_stack.iter().enumerate().rev() {
+            frames.push(self.stack_frame(
+                call_stack.len() - depth,
+                frame.call_site,
+                function(depth),
+            ));
+        }
+        let total = frames.len();
+        json!({ "stackFrames": frames, "totalFrames": total })
+    }
+
+    fn function_name(&self, entry: u16) -> String {
+        self.symbols
+            .as_ref()
+            .and_then(|symbols| symbols.name(entry))
+            .unwrap_or_else(|| format!("sub_{:03X}", entry))
+    }
+
+    fn describe_address(&self, address: u16) -> String {
+        match self.symbols.as_ref().and_then(|s| s.name(address)) {
+            Some(name) => format!("{} ({})", format_address(address), name),
+            None => format_address(address),
+        }
+    }
+
+    fn variables(&self, reference: Option<i64>) -> Value {
+        let registers = self.cpu.registers();
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let pointer = |name: &str, address: u16| {
+            json!({
+                "name": name,
+                "value": self.describe_address(address),
+                "variablesReference": 0,
+                "memoryReference": format_address(address),
+            })
+        };
+
+        let variables: Vec<Value> = match reference {
+            Some(REGISTERS_REFERENCE) => {
+                let mut variables: Vec<Value> = registers
+                    .v
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| {
+                        variable(
+                            format!("V{:X}", index),
+                            format!("{:#04X} ({})", value, value),
+                        )
+                    })
+                    .collect();
+                variables.push(pointer("I", registers.i as u16));
+                variables.push(pointer("PC", registers.pc));
+                variables.push(variable("SP".to_string(), registers.sp.to_string()));
+                variables
+            }
+            Some(TIMERS_REFERENCE) => vec![
+                variable("DT".to_string(), registers.delay_timer.to_string()),
+                variable("ST".to_string(), registers.sound_timer.to_string()),
+            ],
+            Some(STACK_REFERENCE) => self
+                .cpu
+                .call_stack()
+                .iter()
+                .enumerate()
+                .map(|(index, frame)| {
+                    let callee = frame
+                        .entry
+                        .map_or_else(|| "?".to_string(), |entry| self.function_name(entry));
+                    pointer(&format!("[{}] {}", index, callee), frame.return_address)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        json!({ "variables": variables })
+    }
+
+    fn set_variable(&mut self, arguments: &Value) -> Result<Value, String> {
+        let name = arguments["name"].as_str().unwrap_or("");
+        let text = arguments["value"].as_str().unwrap_or("").trim();
+        let value = parse_number(text)
+            .filter(|&value| (0..=0xFFFF).contains(&value))
+            .ok_or_else(|| format!("'{}' is not a number", text))? as u16;
+
+        let mut registers = self.cpu.registers();
+        match arguments["variablesReference"].as_i64() {
+            Some(REGISTERS_REFERENCE) => match name {
+                "I" => registers.i = value as u32,
+                "PC" => registers.pc = value,
+                "SP" => registers.sp = value as usize,
+                _ => {
+                    let index = name
+                        .strip_prefix('V')
+                        .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+                        .filter(|&index| index < 16)
+                        .ok_or_else(|| format!("Cannot set '{}'", name))?;
+                    registers.v[index] = value as u8;
+                }
+            },
+            Some(TIMERS_REFERENCE) => match name {
+                "DT" => registers.delay_timer = value as u8,
+                "ST" => registers.sound_timer = value as u8,
+                _ => return Err(format!("Cannot set '{}'", name)),
+            },
+            _ => return Err(format!("Cannot set '{}'", name)),
+        }
+        self.cpu.set_registers(registers);
+        Ok(json!({ "value": text }))
+    }
+
+    // Evaluates register names and labels, which covers editor hovers
+    fn evaluate(&self, arguments: &Value) -> Result<Value, String> {
+        let expression = arguments["expression"].as_str().unwrap_or("").trim();
+        let registers = self.cpu.registers();
+        let result = match expression.to_ascii_lowercase().as_str() {
+            "i" => self.describe_address(registers.i as u16),
+            "pc" => self.describe_address(registers.pc),
+            "sp" => registers.sp.to_string(),
+            "dt" => registers.delay_timer.to_string(),
+            "st" => registers.sound_timer.to_string(),
+            register if register.len() == 2 && register.starts_with('v') => {
+                let index = usize::from_str_radix(&register[1..], 16)
+                    .map_err(|_| format!("Unknown register '{}'", expression))?;
+                format!("{:#04X} ({})", registers.v[index], registers.v[index])
+            }
+            _ => {
+                let address = self
+                    .symbols
+                    .as_ref()
+                    .and_then(|symbols| symbols.resolve(expression))
+                    .ok_or_else(|| format!("Unknown symbol '{}'", expression))?;
+                format_address(address)
+            }
+        };
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Result<Value, String> {
+        let start = memory_address(arguments)?;
+        let memory = self.cpu.memory();
+        let count = arguments["count"].as_i64().unwrap_or(0).clamp(0, MAX_READ);
+        let first = start.clamp(0, memory.len() as i64);
+        let last = start.saturating_add(count).clamp(0, memory.len() as i64);
+        let data = &memory[first as usize..last as usize];
+        Ok(json!({
+            "address": format!("{:#X}", first),
+            "data": encode_base64(data),
+            "unreadableBytes": count - data.len() as i64,
+        }))
+    }
+
+    fn write_memory(&mut self, arguments: &Value) -> Result<Value, String> {
+        let start = memory_address(arguments)?;
+        let data = decode_base64(arguments["data"].as_str().unwrap_or(""))?;
+        let end = usize::try_from(start)
+            .ok()
+            .and_then(|start| start.checked_add(data.len()));
+        if end.is_none_or(|end| end > self.cpu.memory().len()) {
+            return Err("Write is outside of memory".to_string());
+        }
+        for (offset, &byte) in data.iter().enumerate() {
+            self.cpu.write_memory(start as usize + offset, byte);
+        }
+        Ok(json!({ "bytesWritten": data.len() }))
+    }
+
+    fn disassemble(&self, arguments: &Value) -> Result<Value, String> {
+        let start = arguments["instructionOffset"]
+            .as_i64()
+            .unwrap_or(0)
+            .checked_mul(2)
+            .and_then(|offset| memory_address(arguments).ok()?.checked_add(offset))
+            .ok_or("Disassembly starts outside of memory")?;
+        let memory = self.cpu.memory();
+        // Clients ask for a screenful, so a huge count is a mistake
+        let count = arguments["instructionCount"]
+            .as_i64()
+            .unwrap_or(0)
+            .clamp(0, MAX_DISASSEMBLY);
+
+        let instructions: Vec<Value> = (0..count)
+            .map(|index| start.saturating_add(index * 2))
+            .map(|address| {
+                if address < 0 || address >= memory.len() as i64 - 1 {
+                    return json!({ "address": format!("{:#X}", address), "instruction": "??" });
+                }
+                // MegaChip memory runs past what a u16 can address
+                let address = address as usize;
+                let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+                let mut instruction = json!({
+                    "address": format_address(address),
+                    "instructionBytes": format!("{:04X}", opcode),
+                    "instruction": disassemble(opcode, self.cpu.platform(), self.symbols.as_ref()),
+                });
+                // Symbols only cover the 4K a program's code lives in
+                if let (Some(symbols), Ok(address)) = (&self.symbols, u16::try_from(address)) {
+                    if let Some(name) = symbols.name(address).filter(|name| !name.contains('+')) {
+                        instruction["symbol"] = json!(name);
+                    }
+                    if let (Some(source), Some(line)) = (symbols.source(), symbols.line(address)) {
+                        instruction["location"] = json!({ "path": source });
+                        instruction["line"] = json!(line);
+                    }
+                }
+                instruction
+            })
+            .collect();
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    fn tick(&mut self) {
+        self.cpu.cycle();
+        self.cycle_count += 1;
+        if self.cycle_count < self.cycles_per_frame {
+            return;
+        }
+        self.cycle_count = 0;
+        self.frame_count += 1;
+        self.cpu.end_frame();
+        self.cpu.decrement_timers();
+
+        let elapsed = self.frame_start.elapsed();
+        if elapsed < FRAME_TIME {
+            std::thread::sleep(FRAME_TIME - elapsed);
+        }
+        self.frame_start = Instant::now();
+    }
+
+    fn step(&mut self) {
+        loop {
+            let waiting = self.cpu.is_waiting_for_display();
+            self.tick();
+            if !waiting {
+                break;
+            }
+        }
+        for warning in self.cpu.take_stack_warnings() {
+            self.event(
+                "output",
+                json!({ "category": "console", "output": format!("{}\n", warning) }),
+            );
+        }
+    }
+
+    fn step_instruction(&mut self) {
+        self.step();
+        if self.cpu.halted() {
+            self.exit();
+        } else if self.cpu.fault().is_some() {
+            self.fault();
+        } else {
+            self.stop("step", None);
+        }
+    }
+
+    // Always executes the current instruction first so continuing from a
+    // breakpoint does not stop on it again
+    fn resume(&mut self, resume: Resume) {
+        self.running = Some(resume);
+        self.frame_start = Instant::now();
+        self.step();
+        self.check_stop();
+    }
+
+    fn run_frame(&mut self) {
+        let frame = self.frame_count;
+        while self.running.is_some() && self.frame_count == frame {
+            self.step();
+            self.check_stop();
+        }
+    }
+
+    fn check_stop(&mut self) {
+        let Some(resume) = &self.running else {
+            return;
+        };
+        let pc = self.cpu.pc();
+        let depth = self.cpu.stack().len();
+
+        if self.cpu.halted() {
+            self.exit();
+        } else if self.cpu.fault().is_some() {
+            self.fault();
+        } else if self.source_breakpoints.contains(&pc)
+            || self.function_breakpoints.contains(&pc)
+            || self.instruction_breakpoints.contains(&pc)
+        {
+            self.stop("breakpoint", None);
+        } else {
+            let finished = match *resume {
+                Resume::Continue => false,
+                Resume::StepOver {
+                    pc: target,
+                    depth: target_depth,
+                } => pc == target && depth == target_depth,
+                Resume::StepOut {
+                    depth: target_depth,
+                } => depth < target_depth,
+            };
+            if finished {
+                self.stop("step", None);
+            }
+        }
+    }
+
+    // 00FD ends the program, which ends the debug session
+    fn exit(&mut self) {
+        self.running = None;
+        self.event("exited", json!({ "exitCode": 0 }));
+        self.event("terminated", json!({}));
+    }
+
+    fn fault(&mut self) {
+        let message = self.cpu.fault().unwrap_or_default().to_string();
+        self.stop("exception", Some(message));
+    }
+
+    fn stop(&mut self, reason: &str, text: Option<String>) {
+        self.running = None;
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+            body["description"] = json!(text);
+        }
+        self.event("stopped", body);
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn format_address(address: impl UpperHex) -> String {
+    format!("0x{:03X}", address)
+}
+
+fn parse_reference(text: &str) -> Option<i64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn memory_address(arguments: &Value) -> Result<i64, String> {
+    let reference = arguments["memoryReference"].as_str().unwrap_or("");
+    let base = parse_reference(reference)
+        .ok_or_else(|| format!("Invalid memory reference '{}'", reference))?;
+    base.checked_add(arguments["offset"].as_i64().unwrap_or(0))
+        .ok_or_else(|| format!("Memory reference '{}' is out of range", reference))
+}
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(BASE64[(bits >> (18 - index * 6)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes().filter(|&c| c != b'=') {
+        let value = BASE64
+            .iter()
+            .position(|&b| b == c)
+            .ok_or_else(|| format!("Invalid base64 data '{}'", text))?;
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::mpsc::Sender;
+    use std::thread;
+
+    const SOURCE: &str = ": inc\n  v1 += 1\n;\n: main\n  loop\n    inc\n  again\n";
+
+    // Hands each flushed message to the client thread
+    struct Outbox {
+        sender: Sender<Vec<u8>>,
+        buffer: Vec<u8>,
+    }
+
+    impl Write for Outbox {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.buffer.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            let _ = self.sender.send(std::mem::take(&mut self.buffer));
+            Ok(())
+        }
+    }
+
+    struct Client {
+        requests: Sender<Value>,
+        replies: Receiver<Vec<u8>>,
+        seq: i64,
+    }
+
+    impl Client {
+        fn next(&mut self) -> Value {
+            let message = self.replies.recv().unwrap();
+            let body = read_body(&mut Cursor::new(message)).unwrap();
+            serde_json::from_slice(&body).unwrap()
+        }
+
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let request = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            self.requests.send(request).unwrap();
+            loop {
+                let message = self.next();
+                if message["type"] == "response" {
+                    assert_eq!(message["request_seq"], self.seq);
+                    return message;
+                }
+            }
+        }
+
+        fn wait_for(&mut self, event: &str) -> Value {
+            loop {
+                let message = self.next();
+                if message["event"] == event {
+                    return message;
+                }
+            }
+        }
+    }
+
+    // Runs a session on a source file, with the script as the client
+    fn run_script(
+        source: &str,
+        name: &str,
+        script: impl FnOnce(&mut Client, String) + Send + 'static,
+    ) {
+        let path = std::env::temp_dir().join(format!("dap-{}-{}.8o", name, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let program = path.to_string_lossy().into_owned();
+
+        let (requests, messages) = channel();
+        let (sender, replies) = channel();
+        let client = thread::spawn(move || {
+            let mut client = Client {
+                requests,
+                replies,
+                seq: 0,
+            };
+            script(&mut client, program);
+        });
+
+        let outbox = Outbox {
+            sender,
+            buffer: Vec::new(),
+        };
+        assert_eq!(run_session(messages, Box::new(outbox)), 0);
+        std::fs::remove_file(&path).unwrap();
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn scripted_session_through_the_message_loop() {
+        run_script(SOURCE, "session", |client, program| {
+            let response = client.request("initialize", json!({ "adapterID": "chip8" }));
+            assert_eq!(response["success"], true);
+
+            let launch = json!({ "program": program, "stopOnEntry": true });
+            assert_eq!(client.request("launch", launch)["success"], true);
+            client.wait_for("initialized");
+
+            let breakpoints = json!({
+                "source": { "path": program },
+                "breakpoints": [{ "line": 2 }],
+            });
+            let response = client.request("setBreakpoints", breakpoints);
+            assert_eq!(response["body"]["breakpoints"][0]["verified"], true);
+
+            client.request("configurationDone", json!({}));
+            assert_eq!(client.wait_for("stopped")["body"]["reason"], "entry");
+
+            assert_eq!(client.request("continue", json!({}))["success"], true);
+            assert_eq!(client.wait_for("stopped")["body"]["reason"], "breakpoint");
+
+            let trace = client.request("stackTrace", json!({ "threadId": THREAD_ID }));
+            let frames = &trace["body"]["stackFrames"];
+            assert_eq!(frames[0]["name"], "inc");
+            assert_eq!(frames[0]["line"], 2);
+            assert_eq!(frames[1]["line"], 6);
+
+            // Out of range requests fail or are cut short instead of
+            // overflowing or allocating without limit
+            let far =
+                json!({ "memoryReference": format!("{}", i64::MAX), "offset": 1, "count": 4 });
+            assert_eq!(client.request("readMemory", far)["success"], false);
+            let huge = json!({ "memoryReference": "0x200", "count": i64::MAX });
+            let response = client.request("readMemory", huge);
+            assert_eq!(response["body"]["address"], "0x200");
+            assert_eq!(response["body"]["unreadableBytes"], MAX_READ - 0xE00);
+            let far = json!({ "memoryReference": "0x200", "offset": i64::MAX, "data": "AA==" });
+            assert_eq!(client.request("writeMemory", far)["success"], false);
+            let far = json!({ "memoryReference": "0x200", "instructionOffset": i64::MAX });
+            assert_eq!(client.request("disassemble", far)["success"], false);
+            let huge = json!({
+                "memoryReference": format!("{}", i64::MAX - 1),
+                "instructionCount": i64::MAX,
+            });
+            let response = client.request("disassemble", huge);
+            assert_eq!(
+                response["body"]["instructions"].as_array().unwrap().len(),
+                MAX_DISASSEMBLY as usize
+            );
+
+            assert_eq!(client.request("disconnect", json!({}))["success"], true);
+        });
+    }
+
+    #[test]
+    fn exit_ends_the_session() {
+        let source = ": main\n  v0 := 1\n  0x00 0xFD\n";
+        run_script(source, "exit", |client, program| {
+            client.request("initialize", json!({ "adapterID": "chip8" }));
+            let launch = json!({ "program": program, "stopOnEntry": true, "platform": "schip1.1" });
+            assert_eq!(client.request("launch", launch)["success"], true);
+            client.wait_for("initialized");
+            client.request("configurationDone", json!({}));
+            client.wait_for("stopped");
+
+            client.request("continue", json!({}));
+            assert_eq!(client.wait_for("exited")["body"]["exitCode"], 0);
+            client.wait_for("terminated");
+            assert_eq!(client.request("disconnect", json!({}))["success"], true);
+        });
+    }
+
+    #[test]
+    fn disassembly_reaches_past_64k_on_megachip() {
+        let source = ": main\n  loop again\n";
+        run_script(source, "megachip", |client, program| {
+            client.request("initialize", json!({ "adapterID": "chip8" }));
+            let launch = json!({ "program": program, "stopOnEntry": true, "platform": "megachip" });
+            assert_eq!(client.request("launch", launch)["success"], true);
+            client.wait_for("initialized");
+
+            let far = json!({ "memoryReference": "0x12340", "instructionCount": 2 });
+            let response = client.request("disassemble", far);
+            let instructions = &response["body"]["instructions"];
+            assert_eq!(instructions[0]["address"], "0x12340");
+            assert_eq!(instructions[1]["address"], "0x12342");
+            assert_eq!(client.request("disconnect", json!({}))["success"], true);
+        });
+    }
+
+    #[test]
+    fn oversized_bodies_are_skipped() {
+        let mut stream = format!("Content-Length: {}\r\n\r\n", MAX_BODY_LENGTH + 1).into_bytes();
+        stream.resize(stream.len() + MAX_BODY_LENGTH + 1, b' ');
+        stream.extend_from_slice(b"Content-Length: 2\r\n\r\n{}");
+        let mut reader = Cursor::new(stream);
+        assert_eq!(read_body(&mut reader), Some(b"{}".to_vec()));
+        assert_eq!(read_body(&mut reader), None);
+    }
+}
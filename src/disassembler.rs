@@ -0,0 +1,81 @@
+use crate::instruction::{Instruction, decode_for};
+use crate::platform::Platform;
+use crate::symbols::Symbols;
+
+// Address operands are shown as labels when symbols are available
+pub fn disassemble(opcode: u16, platform: Platform, symbols: Option<&Symbols>) -> String {
+    let Ok(instruction) = decode_for(platform, opcode) else {
+        return format!("DW 0x{:04X}", opcode);
+    };
+    let target = |nnn: u16| {
+        symbols
+            .and_then(|symbols| symbols.name(nnn))
+            .unwrap_or_else(|| format!("0x{:03X}", nnn))
+    };
+
+    match instruction {
+        Instruction::Sys(nnn) => format!("SYS 0x{:03X}", nnn),
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::Jp(nnn) => format!("JP {}", target(nnn)),
+        Instruction::Call(nnn) => format!("CALL {}", target(nnn)),
+        Instruction::SeByte { x, nn } => format!("SE V{:X}, 0x{:02X}", x, nn),
+        Instruction::SneByte { x, nn } => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        Instruction::SeReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::LdByte { x, nn } => format!("LD V{:X}, 0x{:02X}", x, nn),
+        Instruction::AddByte { x, nn } => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        Instruction::LdReg { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::AddReg { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::Shr { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::Subn { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::Shl { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SneReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LdI(nnn) => format!("LD I, {}", target(nnn)),
+        Instruction::JpV0(nnn) => format!("JP V0, {}", target(nnn)),
+        Instruction::Rnd { x, nn } => format!("RND V{:X}, 0x{:02X}", x, nn),
+        Instruction::Drw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::Skp(x) => format!("SKP V{:X}", x),
+        Instruction::Sknp(x) => format!("SKNP V{:X}", x),
+        Instruction::LdVxDt(x) => format!("LD V{:X}, DT", x),
+        Instruction::LdVxK(x) => format!("LD V{:X}, K", x),
+        Instruction::LdDtVx(x) => format!("LD DT, V{:X}", x),
+        Instruction::LdStVx(x) => format!("LD ST, V{:X}", x),
+        Instruction::AddI(x) => format!("ADD I, V{:X}", x),
+        Instruction::LdF(x) => format!("LD F, V{:X}", x),
+        Instruction::LdB(x) => format!("LD B, V{:X}", x),
+        Instruction::LdIVx(x) => format!("LD [I], V{:X}", x),
+        Instruction::LdVxI(x) => format!("LD V{:X}, [I]", x),
+        Instruction::Bgc => "BGC".to_string(),
+        Instruction::AddNib { x, y } => format!("ADDN V{:X}, V{:X}", x, y),
+        Instruction::Col { x, y, n } => format!("COL V{:X}, V{:X}, {}", x, y, n),
+        Instruction::Skp2(x) => format!("SKP2 V{:X}", x),
+        Instruction::Sknp2(x) => format!("SKNP2 V{:X}", x),
+        Instruction::Out(x) => format!("OUT V{:X}", x),
+        Instruction::In(x) => format!("IN V{:X}", x),
+        Instruction::MegaOff => "MEGAOFF".to_string(),
+        Instruction::MegaOn => "MEGAON".to_string(),
+        Instruction::ScrollUp(n) => format!("SCRU {}", n),
+        Instruction::LdHi(nn) => format!("LDHI 0x{:02X}....", nn),
+        Instruction::LdPal(nn) => format!("LDPAL {}", nn),
+        Instruction::SprW(nn) => format!("SPRW {}", nn),
+        Instruction::SprH(nn) => format!("SPRH {}", nn),
+        Instruction::Alpha(nn) => format!("ALPHA 0x{:02X}", nn),
+        Instruction::DigiSnd(n) => format!("DIGISND {}", n),
+        Instruction::StopSnd => "STOPSND".to_string(),
+        Instruction::BMode(n) => format!("BMODE {}", n),
+        Instruction::CCol(nn) => format!("CCOL 0x{:02X}", nn),
+        Instruction::ScrollDown(n) => format!("SCD {}", n),
+        Instruction::ScrollRight => "SCR".to_string(),
+        Instruction::ScrollLeft => "SCL".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::Low => "LOW".to_string(),
+        Instruction::High => "HIGH".to_string(),
+        Instruction::LdHf(x) => format!("LD HF, V{:X}", x),
+        Instruction::LdRVx(x) => format!("LD R, V{:X}", x),
+        Instruction::LdVxR(x) => format!("LD V{:X}, R", x),
+    }
+}
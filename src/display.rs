@@ -1,25 +1,326 @@
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+// SCHIP's 128x64 screen, which also fits the VIP hi-res interpreter's two
+// pages of 32 rows
+pub const MAX_WIDTH: usize = 128;
+pub const MAX_HEIGHT: usize = 64;
+
+// CHIP-8X foreground colors apply to zones 8 pixels wide, and start out red
+pub const ZONE_WIDTH: usize = 8;
+pub const DEFAULT_FOREGROUND: u8 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    // Smallest rectangle covering both
+    pub fn union(self, other: Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+}
+
+pub fn screen_rect(width: usize, height: usize) -> Rect {
+    Rect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    }
+}
+
+// Merges an optional region into another, e.g. when accumulating changes
+pub fn merge(region: Option<Rect>, other: Option<Rect>) -> Option<Rect> {
+    match (region, other) {
+        (Some(a), Some(b)) => Some(a.union(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// A copy of the screen with one byte per pixel, row by row, as handed to
+// renderers and frame logs
+#[derive(Clone, PartialEq)]
+pub struct Snapshot {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+pub fn build_snapshot(width: usize, height: usize) -> Snapshot {
+    Snapshot {
+        width,
+        height,
+        pixels: vec![0; width * height],
+    }
+}
+
+impl Snapshot {
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn bounds(&self) -> Rect {
+        screen_rect(self.width, self.height)
+    }
+}
+
+// The state of the CHIP-8X color board: one of four background colors, and
+// one of eight foreground colors for each zone of each row
+#[derive(Clone)]
+pub struct ColorMap {
+    background: u8,
+    zones: [[u8; WIDTH / ZONE_WIDTH]; HEIGHT],
+}
+
+impl ColorMap {
+    pub fn background(&self) -> u8 {
+        self.background
+    }
+
+    pub fn foreground(&self, x: usize, y: usize) -> u8 {
+        self.zones[y][x / ZONE_WIDTH]
+    }
+}
+
+// Each row is packed into a u128 with the leftmost pixel in the top bit, so a
+// sprite row is drawn with one XOR. Narrower screens use the top bits. The
+// buffer also remembers the bounding box of pixels touched since the last
+// take_dirty, so frontends can redraw only that part of the screen.
 pub struct Display {
-    buffer: [[u8; 64]; 32],
+    rows: [u128; MAX_HEIGHT],
+    width: usize,
+    height: usize,
+    dirty: Option<Rect>,
+    colors: Option<ColorMap>,
 }
 
 pub fn build_display() -> Display {
     Display {
-        buffer: [[0; 64]; 32],
+        rows: [0; MAX_HEIGHT],
+        width: WIDTH,
+        height: HEIGHT,
+        dirty: None,
+        colors: None,
     }
 }
 
 impl Display {
-    pub fn get_display(&self) -> [[u8; 64]; 32] {
-        self.buffer
+    pub fn get_display(&self) -> Snapshot {
+        let mut snapshot = build_snapshot(self.width, self.height);
+        for (pixels, row) in snapshot.pixels.chunks_mut(self.width).zip(self.rows) {
+            for (x, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = (row >> (127 - x)) as u8 & 1;
+            }
+        }
+        snapshot
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bounds(&self) -> Rect {
+        screen_rect(self.width, self.height)
+    }
+
+    // Switches to a larger or smaller screen, which starts out blank
+    pub fn set_size(&mut self, width: usize, height: usize) {
+        self.width = width.min(MAX_WIDTH);
+        self.height = height.min(MAX_HEIGHT);
+        self.rows = [0; MAX_HEIGHT];
+        self.dirty = Some(self.bounds());
+    }
+
+    // Lit bits for the columns on screen
+    fn row_mask(&self) -> u128 {
+        !0 << (MAX_WIDTH - self.width)
     }
 
     pub fn set_pixel(&mut self, x: usize, y: usize) -> bool {
-        let was_on = self.buffer[y][x] == 1;
-        self.buffer[y][x] ^= 1;
-        was_on
+        self.blit(y, 1 << (127 - x))
+    }
+
+    // Draws up to 32 pixels of a sprite row, given in the top bits, at x.
+    // The row is either cut off at the right edge or wrapped around to the
+    // left. Returns whether any lit pixel was erased.
+    pub fn draw_row(&mut self, x: usize, y: usize, sprite: u32, clip: bool) -> bool {
+        let bits = (sprite as u128) << 96;
+        let mut mask = bits >> x;
+        if !clip {
+            mask |= bits.checked_shl((self.width - x) as u32).unwrap_or(0);
+        }
+        self.blit(y, mask & self.row_mask())
+    }
+
+    fn blit(&mut self, y: usize, mask: u128) -> bool {
+        if mask == 0 {
+            return false;
+        }
+        let collision = self.rows[y] & mask != 0;
+        self.rows[y] ^= mask;
+
+        let left = mask.leading_zeros() as usize;
+        let right = 127 - mask.trailing_zeros() as usize;
+        let changed = Rect {
+            x: left,
+            y,
+            width: right - left + 1,
+            height: 1,
+        };
+        self.dirty = merge(self.dirty, Some(changed));
+        collision
     }
 
     pub fn clear(&mut self) {
-        self.buffer = [[0; 64]; 32]
+        if self.rows.iter().any(|&row| row != 0) {
+            self.dirty = Some(self.bounds());
+        }
+        self.rows = [0; MAX_HEIGHT]
+    }
+
+    pub fn colors(&self) -> Option<&ColorMap> {
+        self.colors.as_ref()
+    }
+
+    pub fn enable_colors(&mut self) {
+        self.colors = Some(ColorMap {
+            background: 0,
+            zones: [[DEFAULT_FOREGROUND; WIDTH / ZONE_WIDTH]; HEIGHT],
+        });
+        self.dirty = Some(self.bounds());
+    }
+
+    pub fn cycle_background(&mut self) {
+        if let Some(colors) = &mut self.colors {
+            colors.background = (colors.background + 1) % 4;
+            self.dirty = Some(self.bounds());
+        }
+    }
+
+    // Colors a block of zones, given in zone columns and pixel rows. Both
+    // wrap around the edges of the screen.
+    pub fn color_zones(&mut self, column: usize, row: usize, size: (usize, usize), color: u8) {
+        let Some(colors) = &mut self.colors else {
+            return;
+        };
+        let columns = colors.zones[0].len();
+        for y in (row..row + size.1).map(|y| y % HEIGHT) {
+            for x in (column..column + size.0).map(|x| x % columns) {
+                colors.zones[y][x] = color & 7;
+                let zone = Rect {
+                    x: x * ZONE_WIDTH,
+                    y,
+                    width: ZONE_WIDTH,
+                    height: 1,
+                };
+                self.dirty = merge(self.dirty, Some(zone));
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        let rows = rows.min(self.height);
+        self.rows.copy_within(0..self.height - rows, rows);
+        self.rows[..rows].fill(0);
+        self.dirty = Some(self.bounds());
+    }
+
+    pub fn scroll_right(&mut self, columns: usize) {
+        let mask = self.row_mask();
+        for row in &mut self.rows {
+            *row = (*row >> columns) & mask;
+        }
+        self.dirty = Some(self.bounds());
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        for row in &mut self.rows {
+            *row <<= columns;
+        }
+        self.dirty = Some(self.bounds());
+    }
+
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPRITES: [u32; 4] = [0xFF00_0000, 0x8100_0000, 0xA5C3_0000, 0xF0F0_A5A5];
+    // Already lit, so some draws collide
+    const BACKGROUND: u128 = 0x0F0F_3C3C_0000_FFFF_8001_0000_1234_5678;
+
+    // Draws a sprite row a pixel at a time, as draw_row replaced
+    fn draw_pixels(display: &mut Display, x: usize, y: usize, sprite: u32, clip: bool) -> bool {
+        let mut collision = false;
+        for column in 0..32 {
+            let mut pixel = x + column;
+            if pixel >= display.width() {
+                if clip {
+                    break;
+                }
+                pixel %= display.width();
+            }
+            if sprite & (0x8000_0000 >> column) != 0 && display.set_pixel(pixel, y) {
+                collision = true;
+            }
+        }
+        collision
+    }
+
+    fn display_of_width(width: usize) -> Display {
+        let mut display = build_display();
+        display.set_size(width, MAX_HEIGHT);
+        for x in 0..width {
+            if BACKGROUND & (1 << (127 - x)) != 0 {
+                display.set_pixel(x, 3);
+            }
+        }
+        display.take_dirty();
+        display
+    }
+
+    #[test]
+    fn draw_row_matches_drawing_pixel_by_pixel() {
+        for width in [WIDTH, MAX_WIDTH] {
+            for clip in [false, true] {
+                for sprite in SPRITES {
+                    for x in 0..width {
+                        let mut rows = display_of_width(width);
+                        let mut pixels = display_of_width(width);
+                        let context =
+                            format!("width {} clip {} x {} {:#x}", width, clip, x, sprite);
+                        assert_eq!(
+                            rows.draw_row(x, 3, sprite, clip),
+                            draw_pixels(&mut pixels, x, 3, sprite, clip),
+                            "{}",
+                            context
+                        );
+                        assert_eq!(rows.rows, pixels.rows, "{}", context);
+                        assert_eq!(rows.take_dirty(), pixels.take_dirty(), "{}", context);
+                    }
+                }
+            }
+        }
     }
 }
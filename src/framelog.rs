@@ -0,0 +1,329 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::display::{Snapshot, build_snapshot};
+
+const MAGIC: &[u8; 4] = b"C8FL";
+const VERSION: u8 = 3;
+const HEADER_LENGTH: usize = 5;
+const MAX_REPORTED_PIXELS: usize = 32;
+const TRACE_CONTEXT: usize = 16;
+
+pub struct Frame {
+    pub display: Snapshot,
+    pub instructions: Vec<(u16, u16)>,
+}
+
+// Each frame is stored as its screen size, the instructions executed during
+// it and the XOR of its display with the previous frame, with runs of
+// unchanged pixels collapsed into (0, length) pairs. A frame whose size
+// differs from the previous one is XORed with a blank screen instead.
+pub struct FrameLogger {
+    writer: BufWriter<File>,
+    previous: Snapshot,
+    instructions: Vec<(u16, u16)>,
+}
+
+pub fn create_frame_log(location: &str) -> Result<FrameLogger, String> {
+    let file = File::create(location)
+        .map_err(|e| format!("Failed to create frame log '{}': {}", location, e))?;
+    let mut writer = BufWriter::new(file);
+    writer
+        .write_all(MAGIC)
+        .and_then(|_| writer.write_all(&[VERSION]))
+        .map_err(|e| format!("Failed to write frame log '{}': {}", location, e))?;
+
+    Ok(FrameLogger {
+        writer,
+        previous: build_snapshot(0, 0),
+        instructions: Vec::new(),
+    })
+}
+
+impl FrameLogger {
+    pub fn record_instruction(&mut self, pc: u16, opcode: u16) {
+        self.instructions.push((pc, opcode));
+    }
+
+    pub fn record_frame(&mut self, display: &Snapshot) -> Result<(), String> {
+        if (display.width, display.height) != (self.previous.width, self.previous.height) {
+            self.previous = build_snapshot(display.width, display.height);
+        }
+        let mut record = Vec::new();
+        record.extend_from_slice(&(display.width as u16).to_be_bytes());
+        record.extend_from_slice(&(display.height as u16).to_be_bytes());
+        record.extend_from_slice(&(self.instructions.len() as u32).to_be_bytes());
+        for (pc, opcode) in self.instructions.drain(..) {
+            record.extend_from_slice(&pc.to_be_bytes());
+            record.extend_from_slice(&opcode.to_be_bytes());
+        }
+
+        let delta: Vec<u8> = display
+            .pixels
+            .iter()
+            .zip(&self.previous.pixels)
+            .map(|(current, previous)| current ^ previous)
+            .collect();
+        let compressed = compress(&delta);
+        record.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        record.extend_from_slice(&compressed);
+
+        self.previous = display.clone();
+        self.writer
+            .write_all(&record)
+            .map_err(|e| format!("Failed to write frame log: {}", e))
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .map_err(|e| format!("Failed to write frame log: {}", e))
+    }
+}
+
+fn compress(delta: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut run = 0u8;
+    for &byte in delta {
+        if byte == 0 {
+            run += 1;
+            if run == u8::MAX {
+                output.extend_from_slice(&[0, run]);
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            output.extend_from_slice(&[0, run]);
+            run = 0;
+        }
+        output.push(byte);
+    }
+    if run > 0 {
+        output.extend_from_slice(&[0, run]);
+    }
+    output
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == 0 {
+            let run = *bytes.next().ok_or("Truncated run in frame log")?;
+            output.extend(std::iter::repeat_n(0, run as usize));
+        } else {
+            output.push(byte);
+        }
+    }
+    Ok(output)
+}
+
+struct LogReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    location: &'a str,
+}
+
+impl<'a> LogReader<'a> {
+    fn is_at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let slice = self
+            .data
+            .get(self.pos..self.pos + count)
+            .ok_or_else(|| format!("Frame log '{}' is truncated", self.location))?;
+        self.pos += count;
+        Ok(slice)
+    }
+}
+
+pub fn read_frame_log(location: &str) -> Result<Vec<Frame>, String> {
+    let file = File::open(location)
+        .map_err(|e| format!("Failed to open frame log '{}': {}", location, e))?;
+    let mut data = Vec::new();
+    BufReader::new(file)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("Failed to read frame log '{}': {}", location, e))?;
+
+    if data.len() < HEADER_LENGTH || &data[0..4] != MAGIC || data[4] != VERSION {
+        return Err(format!("'{}' is not a frame log", location));
+    }
+
+    let mut reader = LogReader {
+        data: &data,
+        pos: HEADER_LENGTH,
+        location,
+    };
+
+    let mut frames = Vec::new();
+    let mut display = build_snapshot(0, 0);
+    while !reader.is_at_end() {
+        let size = reader.take(4)?;
+        let width = u16::from_be_bytes([size[0], size[1]]) as usize;
+        let height = u16::from_be_bytes([size[2], size[3]]) as usize;
+        if (width, height) != (display.width, display.height) {
+            display = build_snapshot(width, height);
+        }
+
+        let header = reader.take(4)?;
+        let count = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let mut instructions = Vec::with_capacity(count);
+        for entry in reader.take(count * 4)?.chunks_exact(4) {
+            let pc = u16::from_be_bytes([entry[0], entry[1]]);
+            let opcode = u16::from_be_bytes([entry[2], entry[3]]);
+            instructions.push((pc, opcode));
+        }
+
+        let length = reader.take(4)?;
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        let delta = decompress(reader.take(length)?)?;
+        if delta.len() != display.pixels.len() {
+            return Err(format!("Frame log '{}' has a corrupt frame", location));
+        }
+        for (pixel, change) in display.pixels.iter_mut().zip(delta) {
+            *pixel ^= change;
+        }
+
+        frames.push(Frame {
+            display: display.clone(),
+            instructions,
+        });
+    }
+    Ok(frames)
+}
+
+// Compares two frame logs and prints where they first diverge. Returns the
+// process exit code: 0 when identical, 1 when they differ, 2 on error.
+pub fn diff_command(args: &[String]) -> i32 {
+    let [first, second] = args else {
+        eprintln!("Usage: rust-chip8 diff-frames <LOG A> <LOG B>");
+        return 2;
+    };
+
+    let (a, b) = match (read_frame_log(first), read_frame_log(second)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    for (index, (frame_a, frame_b)) in a.iter().zip(&b).enumerate() {
+        let (width, height) = (frame_a.display.width, frame_a.display.height);
+        let size_b = (frame_b.display.width, frame_b.display.height);
+        if (width, height) != size_b {
+            println!(
+                "Frame {} is {}x{} in '{}' but {}x{} in '{}'",
+                index, width, height, first, size_b.0, size_b.1, second
+            );
+            print_trace(first, &a, index);
+            print_trace(second, &b, index);
+            return 1;
+        }
+        let differences: Vec<(usize, usize)> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| frame_a.display.pixel(x, y) != frame_b.display.pixel(x, y))
+            .collect();
+        if differences.is_empty() {
+            continue;
+        }
+
+        println!("Frame {} differs in {} pixel(s):", index, differences.len());
+        for &(x, y) in differences.iter().take(MAX_REPORTED_PIXELS) {
+            println!(
+                "  ({:2}, {:2}): {} -> {}",
+                x,
+                y,
+                frame_a.display.pixel(x, y),
+                frame_b.display.pixel(x, y)
+            );
+        }
+        if differences.len() > MAX_REPORTED_PIXELS {
+            println!("  ... and {} more", differences.len() - MAX_REPORTED_PIXELS);
+        }
+
+        print_trace(first, &a, index);
+        print_trace(second, &b, index);
+        return 1;
+    }
+
+    if a.len() != b.len() {
+        println!(
+            "Logs match for {} frames, but '{}' has {} frames and '{}' has {}",
+            a.len().min(b.len()),
+            first,
+            a.len(),
+            second,
+            b.len()
+        );
+        return 1;
+    }
+
+    println!("Logs are identical ({} frames)", a.len());
+    0
+}
+
+fn print_trace(name: &str, frames: &[Frame], index: usize) {
+    let start = index.saturating_sub(1);
+    let recent: Vec<(usize, (u16, u16))> = frames[start..=index]
+        .iter()
+        .enumerate()
+        .flat_map(|(offset, frame)| {
+            frame
+                .instructions
+                .iter()
+                .map(move |&instruction| (start + offset, instruction))
+        })
+        .collect();
+
+    println!("Last instructions in '{}':", name);
+    for (frame, (pc, opcode)) in &recent[recent.len().saturating_sub(TRACE_CONTEXT)..] {
+        println!("  frame {:5}  {:#05x}: {:04x}", frame, pc, opcode);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(width: usize, height: usize, lit: &[usize]) -> Snapshot {
+        let mut snapshot = build_snapshot(width, height);
+        for &index in lit {
+            snapshot.pixels[index] = 1;
+        }
+        snapshot
+    }
+
+    #[test]
+    fn each_frame_keeps_its_own_size() {
+        let location = std::env::temp_dir().join(format!("frames-{}.log", std::process::id()));
+        let location = location.to_str().unwrap();
+        let recorded = [
+            snapshot(64, 32, &[0, 5]),
+            snapshot(128, 64, &[]),
+            snapshot(128, 64, &[200]),
+            snapshot(64, 32, &[5]),
+        ];
+
+        let mut log = create_frame_log(location).unwrap();
+        for (index, display) in recorded.iter().enumerate() {
+            log.record_instruction(0x200 + index as u16 * 2, 0x00E0);
+            log.record_frame(display).unwrap();
+        }
+        log.finish().unwrap();
+
+        let frames = read_frame_log(location);
+        std::fs::remove_file(location).unwrap();
+        let frames = frames.unwrap();
+        assert_eq!(frames.len(), recorded.len());
+        for (index, (frame, display)) in frames.iter().zip(&recorded).enumerate() {
+            assert_eq!(frame.display.width, display.width);
+            assert_eq!(frame.display.height, display.height);
+            assert_eq!(frame.display.pixels, display.pixels);
+            assert_eq!(frame.instructions, [(0x200 + index as u16 * 2, 0x00E0)]);
+        }
+    }
+}
@@ -0,0 +1,706 @@
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::cpu::{Cpu, Registers};
+
+const FRAME_TIME: Duration = Duration::from_nanos(16_666_667);
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// Register numbers as seen by the debugger: V0-VF, then I, PC, SP, DT, ST.
+// I and PC are 16 bits wide, everything else 8.
+const REGISTER_COUNT: usize = 21;
+const I_REGISTER: usize = 16;
+const PC_REGISTER: usize = 17;
+const SP_REGISTER: usize = 18;
+const DT_REGISTER: usize = 19;
+const ST_REGISTER: usize = 20;
+
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    fn fill(&mut self) -> std::io::Result<usize> {
+        let mut chunk = [0u8; 1024];
+        let count = self.stream.read(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..count]);
+        Ok(count)
+    }
+
+    // Takes the next complete packet out of the buffer and acknowledges it.
+    // Stray acknowledgements from the client are dropped.
+    // Packets with a bad checksum are refused and skipped
+    fn parse(&mut self) -> std::io::Result<Option<Packet>> {
+        loop {
+            match self.buffer.first() {
+                None => return Ok(None),
+                Some(0x03) => {
+                    self.buffer.remove(0);
+                    return Ok(Some(Packet::Interrupt));
+                }
+                Some(b'$') => {}
+                Some(_) => {
+                    self.buffer.remove(0);
+                    continue;
+                }
+            }
+
+            let Some(end) = self.buffer.iter().position(|&b| b == b'#') else {
+                return Ok(None);
+            };
+            if self.buffer.len() < end + 3 {
+                return Ok(None);
+            }
+            let data = self.buffer[1..end].to_vec();
+            let checksum = std::str::from_utf8(&self.buffer[end + 1..end + 3])
+                .ok()
+                .and_then(|text| u8::from_str_radix(text, 16).ok());
+            self.buffer.drain(..end + 3);
+
+            if checksum != Some(checksum_of(&data)) {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+            return Ok(Some(Packet::Command(
+                String::from_utf8_lossy(&data).into_owned(),
+            )));
+        }
+    }
+
+    // Blocks until a packet arrives; None means the client disconnected
+    fn receive(&mut self) -> std::io::Result<Option<Packet>> {
+        loop {
+            if let Some(packet) = self.parse()? {
+                return Ok(Some(packet));
+            }
+            if self.fill()? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    // Checks for a Ctrl-C from the client without blocking
+    fn interrupted(&mut self) -> std::io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let result = self.fill();
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => return Ok(true),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        match self.buffer.iter().position(|&b| b == 0x03) {
+            Some(position) => {
+                self.buffer.remove(position);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn send(&mut self, data: &str) -> std::io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+struct Session<'a> {
+    cpu: &'a mut Cpu,
+    breakpoints: HashSet<u16>,
+    cycles_per_frame: usize,
+    cycle_count: usize,
+    frame_count: u64,
+    frame_start: Instant,
+}
+
+// Serves a single debugger connection on localhost, running the CPU at the
+// normal 60 Hz frame rate whenever the debugger continues it.
+pub fn serve(cpu: &mut Cpu, port: u16, cycles_per_frame: usize) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+    println!("Waiting for GDB on 127.0.0.1:{}", port);
+    serve_listener(cpu, listener, cycles_per_frame)
+}
+
+fn serve_listener(
+    cpu: &mut Cpu,
+    listener: TcpListener,
+    cycles_per_frame: usize,
+) -> Result<(), String> {
+    let (stream, address) = listener
+        .accept()
+        .map_err(|e| format!("Failed to accept GDB connection: {}", e))?;
+    println!("GDB connected from {}", address);
+
+    let mut connection = Connection {
+        stream,
+        buffer: Vec::new(),
+    };
+    let mut session = Session {
+        cpu,
+        breakpoints: HashSet::new(),
+        cycles_per_frame: cycles_per_frame.max(1),
+        cycle_count: 0,
+        frame_count: 0,
+        frame_start: Instant::now(),
+    };
+
+    loop {
+        let command = match connection.receive() {
+            Ok(Some(Packet::Command(command))) => command,
+            Ok(Some(Packet::Interrupt)) => continue,
+            Ok(None) => break,
+            Err(e) => return Err(format!("GDB connection failed: {}", e)),
+        };
+        match session.handle(&command, &mut connection) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => return Err(format!("GDB connection failed: {}", e)),
+        }
+    }
+    println!("GDB disconnected");
+    Ok(())
+}
+
+impl Session<'_> {
+    // Returns false once the debugger kills or detaches from the target
+    fn handle(&mut self, command: &str, connection: &mut Connection) -> std::io::Result<bool> {
+        let (kind, args) = command.split_at(command.chars().next().map_or(0, char::len_utf8));
+        let reply = match kind {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.set_breakpoint(args, kind == "Z"),
+            "s" | "c" => {
+                if let Some(address) = parse_hex(args) {
+                    let mut registers = self.cpu.registers();
+                    registers.pc = address as u16;
+                    self.cpu.set_registers(registers);
+                }
+                let signal = if kind == "s" {
+                    self.step();
+                    if self.cpu.fault().is_some() {
+                        SIGILL
+                    } else {
+                        SIGTRAP
+                    }
+                } else {
+                    self.run(connection)?
+                };
+                // 00FD ends the program, which GDB sees as the process exiting
+                if self.cpu.halted() {
+                    "W00".to_string()
+                } else {
+                    format!("S{:02x}", signal)
+                }
+            }
+            "k" => return Ok(false),
+            "D" => {
+                connection.send("OK")?;
+                return Ok(false);
+            }
+            "H" | "T" => "OK".to_string(),
+            "q" => self.query(args),
+            _ => String::new(),
+        };
+        connection.send(&reply)?;
+        Ok(true)
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return "PacketSize=4000;qXfer:features:read+".to_string();
+        }
+        if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let Some((offset, length)) = range.split_once(',') else {
+                return "E01".to_string();
+            };
+            let (Some(offset), Some(length)) = (parse_hex(offset), parse_hex(length)) else {
+                return "E01".to_string();
+            };
+            let Some(end) = offset.checked_add(length) else {
+                return "E01".to_string();
+            };
+            let xml = target_xml();
+            let chunk = xml.get(offset.min(xml.len())..end.min(xml.len()));
+            let chunk = chunk.unwrap_or("");
+            let marker = if end >= xml.len() { 'l' } else { 'm' };
+            return format!("{}{}", marker, chunk);
+        }
+        // "monitor stack" prints the call stack GDB cannot unwind by itself
+        if let Some(command) = args.strip_prefix("Rcmd,") {
+            let command = decode_hex_text(command).unwrap_or_default();
+            return match command.trim() {
+                "stack" => encode_hex_text(&self.describe_stack()),
+                _ => encode_hex_text("Commands: stack\n"),
+            };
+        }
+        match args {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn describe_stack(&self) -> String {
+        let call_stack = self.cpu.call_stack();
+        let mut text = format!("#0  {:03X}\n", self.cpu.pc());
+        for (depth, frame) in call_stack.iter().rev().enumerate() {
+            let entry = frame
+                .entry
+                .map_or_else(|| "???".to_string(), |entry| format!("{:03X}", entry));
+            text.push_str(&format!(
+                "#{}  {:03X}  CALL {}\n",
+                depth + 1,
+                frame.call_site,
+                entry
+            ));
+        }
+        text
+    }
+
+    fn register_value(&self, registers: &Registers, number: usize) -> Option<(u32, usize)> {
+        let value = match number {
+            0..=15 => registers.v[number] as u32,
+            I_REGISTER => registers.i,
+            PC_REGISTER => registers.pc as u32,
+            SP_REGISTER => registers.sp as u32,
+            DT_REGISTER => registers.delay_timer as u32,
+            ST_REGISTER => registers.sound_timer as u32,
+            _ => return None,
+        };
+        Some((value, register_size(number)))
+    }
+
+    fn read_registers(&self) -> String {
+        let registers = self.cpu.registers();
+        (0..REGISTER_COUNT)
+            .filter_map(|number| self.register_value(&registers, number))
+            .map(|(value, size)| encode_le(value, size))
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let mut registers = self.cpu.registers();
+        let mut position = 0;
+        for number in 0..REGISTER_COUNT {
+            let size = register_size(number);
+            let Some(value) = args.get(position..position + size * 2).and_then(decode_le) else {
+                return "E01".to_string();
+            };
+            set_register(&mut registers, number, value);
+            position += size * 2;
+        }
+        self.cpu.set_registers(registers);
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        let registers = self.cpu.registers();
+        parse_hex(args)
+            .and_then(|number| self.register_value(&registers, number))
+            .map_or("E01".to_string(), |(value, size)| encode_le(value, size))
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((number, value)) = args.split_once('=') else {
+            return "E01".to_string();
+        };
+        let (Some(number), Some(value)) = (parse_hex(number), decode_le(value)) else {
+            return "E01".to_string();
+        };
+        if number >= REGISTER_COUNT {
+            return "E01".to_string();
+        }
+        let mut registers = self.cpu.registers();
+        set_register(&mut registers, number, value);
+        self.cpu.set_registers(registers);
+        "OK".to_string()
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let memory = self.cpu.memory();
+        let Some((address, length)) = parse_range(args) else {
+            return "E01".to_string();
+        };
+        let Some(end) = address.checked_add(length) else {
+            return "E01".to_string();
+        };
+        if address >= memory.len() {
+            return "E01".to_string();
+        }
+        let end = end.min(memory.len());
+        memory[address..end]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".to_string();
+        };
+        let Some((address, length)) = parse_range(range) else {
+            return "E01".to_string();
+        };
+        let size = self.cpu.memory().len();
+        let (Some(end), Some(digits)) = (address.checked_add(length), length.checked_mul(2)) else {
+            return "E01".to_string();
+        };
+        if address >= size || end > size || data.len() != digits {
+            return "E01".to_string();
+        }
+        let mut bytes = Vec::with_capacity(length);
+        for offset in 0..length {
+            let digits = data.get(offset * 2..offset * 2 + 2);
+            match digits.map(|digits| u8::from_str_radix(digits, 16)) {
+                Some(Ok(byte)) => bytes.push(byte),
+                _ => return "E01".to_string(),
+            }
+        }
+        for (offset, byte) in bytes.into_iter().enumerate() {
+            self.cpu.write_memory(address + offset, byte);
+        }
+        "OK".to_string()
+    }
+
+    // Software (0) and hardware (1) breakpoints are treated the same, since
+    // the emulator checks PC before every instruction either way
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let mut parts = args.split(',');
+        let (Some(kind), Some(address)) = (parts.next(), parts.next().and_then(parse_hex)) else {
+            return "E01".to_string();
+        };
+        if kind != "0" && kind != "1" {
+            return String::new();
+        }
+        if insert {
+            self.breakpoints.insert(address as u16);
+        } else {
+            self.breakpoints.remove(&(address as u16));
+        }
+        "OK".to_string()
+    }
+
+    fn tick(&mut self) {
+        self.cpu.cycle();
+        self.cycle_count += 1;
+        if self.cycle_count < self.cycles_per_frame {
+            return;
+        }
+        self.cycle_count = 0;
+        self.frame_count += 1;
+        self.cpu.end_frame();
+        self.cpu.decrement_timers();
+
+        let elapsed = self.frame_start.elapsed();
+        if elapsed < FRAME_TIME {
+            std::thread::sleep(FRAME_TIME - elapsed);
+        }
+        self.frame_start = Instant::now();
+    }
+
+    // Executes exactly one instruction, sitting out the rest of the frame if
+    // the display-wait quirk is holding the CPU
+    fn step(&mut self) {
+        loop {
+            let waiting = self.cpu.is_waiting_for_display();
+            self.tick();
+            if !waiting {
+                break;
+            }
+        }
+        for warning in self.cpu.take_stack_warnings() {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+
+    fn run(&mut self, connection: &mut Connection) -> std::io::Result<u8> {
+        self.frame_start = Instant::now();
+        self.step();
+        let mut checked_frame = self.frame_count;
+        loop {
+            if self.cpu.fault().is_some() {
+                return Ok(SIGILL);
+            }
+            if self.cpu.halted() {
+                return Ok(SIGTRAP);
+            }
+            if self.breakpoints.contains(&self.cpu.pc()) {
+                return Ok(SIGTRAP);
+            }
+            if self.frame_count != checked_frame {
+                checked_frame = self.frame_count;
+                if connection.interrupted()? {
+                    return Ok(SIGINT);
+                }
+            }
+            self.step();
+        }
+    }
+}
+
+// Bytes each register takes in the g and G packets. I is 32 bits so that
+// MegaChip's 24-bit I fits.
+fn register_size(number: usize) -> usize {
+    match number {
+        I_REGISTER => 4,
+        PC_REGISTER => 2,
+        _ => 1,
+    }
+}
+
+fn set_register(registers: &mut Registers, number: usize, value: u32) {
+    match number {
+        0..=15 => registers.v[number] = value as u8,
+        I_REGISTER => registers.i = value,
+        PC_REGISTER => registers.pc = value as u16,
+        SP_REGISTER => registers.sp = value as usize,
+        DT_REGISTER => registers.delay_timer = value as u8,
+        ST_REGISTER => registers.sound_timer = value as u8,
+        _ => {}
+    }
+}
+
+fn target_xml() -> String {
+    let mut registers = String::new();
+    for v in 0..16 {
+        registers.push_str(&format!(
+            "<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>",
+            v, v
+        ));
+    }
+    registers.push_str(&format!(
+        "<reg name=\"i\" bitsize=\"32\" type=\"data_ptr\" regnum=\"{}\"/>\
+         <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\" regnum=\"{}\"/>\
+         <reg name=\"sp\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\
+         <reg name=\"dt\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\
+         <reg name=\"st\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>",
+        I_REGISTER, PC_REGISTER, SP_REGISTER, DT_REGISTER, ST_REGISTER
+    ));
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8.core\">{}</feature></target>",
+        registers
+    )
+}
+
+// Multi-byte registers are sent little-endian, as GDB expects
+fn encode_le(value: u32, size: usize) -> String {
+    value.to_le_bytes()[..size]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn decode_le(text: &str) -> Option<u32> {
+    if text.is_empty() || text.len() > 8 || !text.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut value = 0u32;
+    for (index, offset) in (0..text.len()).step_by(2).enumerate() {
+        let byte = u8::from_str_radix(text.get(offset..offset + 2)?, 16).ok()?;
+        value |= (byte as u32) << (index * 8);
+    }
+    Some(value)
+}
+
+fn encode_hex_text(text: &str) -> String {
+    text.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex_text(text: &str) -> Option<String> {
+    let bytes = (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::build_cpu;
+    use crate::platform::Platform;
+    use std::thread;
+
+    // Sends a packet and returns the reply's data, acknowledging it
+    fn exchange(stream: &mut TcpStream, command: &str) -> String {
+        let packet = format!("${}#{:02x}", command, checksum_of(command.as_bytes()));
+        stream.write_all(packet.as_bytes()).unwrap();
+        let mut byte = [0u8];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        let mut reply = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0u8; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{:02x}", checksum_of(&reply))
+        );
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn scripted_session_over_localhost() {
+        let mut cpu = build_cpu();
+        // V0 = 1, V1 = 2, V2 = 3, then loop forever at 0x206
+        cpu.load_program(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x06]);
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let registers = exchange(&mut stream, "g");
+            // V0-VF, then I and PC little-endian, then SP, DT and ST
+            assert_eq!(registers.len(), 16 * 2 + 8 + 4 + 3 * 2);
+            assert_eq!(&registers[40..44], "0002");
+
+            let written = format!("aa{}", &registers[2..]);
+            assert_eq!(exchange(&mut stream, &format!("G{}", written)), "OK");
+            assert_eq!(exchange(&mut stream, "g"), written);
+            assert_eq!(exchange(&mut stream, "G00"), "E01");
+
+            assert_eq!(exchange(&mut stream, "m200,4"), "60016102");
+            assert_eq!(exchange(&mut stream, "M300,2:abcd"), "OK");
+            assert_eq!(exchange(&mut stream, "m300,2"), "abcd");
+            assert_eq!(exchange(&mut stream, "mfff,4"), "00");
+
+            // Ranges that overflow, or start past the end of memory
+            assert_eq!(exchange(&mut stream, "m10,ffffffffffffffff"), "E01");
+            assert_eq!(exchange(&mut stream, "mffffffffffffffff,2"), "E01");
+            assert_eq!(exchange(&mut stream, "Mffffffffffffffff,2:abcd"), "E01");
+            assert_eq!(exchange(&mut stream, "M100,ffffffffffffffff:00"), "E01");
+            assert_eq!(exchange(&mut stream, "M1000,0:"), "E01");
+            assert_eq!(exchange(&mut stream, "M300,2:abc"), "E01");
+            assert_eq!(exchange(&mut stream, "M300,2:zzzz"), "E01");
+            assert_eq!(
+                exchange(
+                    &mut stream,
+                    "qXfer:features:read:target.xml:10,ffffffffffffffff"
+                ),
+                "E01"
+            );
+            assert!(exchange(&mut stream, "qXfer:features:read:target.xml:0,10").starts_with('m'));
+
+            assert_eq!(exchange(&mut stream, "Z0,204,2"), "OK");
+            assert_eq!(exchange(&mut stream, "c"), "S05");
+            assert_eq!(exchange(&mut stream, "p11"), "0402");
+            assert_eq!(exchange(&mut stream, "p1"), "02");
+            assert_eq!(exchange(&mut stream, "s"), "S05");
+            assert_eq!(exchange(&mut stream, "p11"), "0602");
+            assert_eq!(exchange(&mut stream, "p2"), "03");
+            assert_eq!(exchange(&mut stream, "z0,204,2"), "OK");
+
+            stream.write_all(b"$k#6b").unwrap();
+        });
+
+        serve_listener(&mut cpu, listener, 10).unwrap();
+        client.join().unwrap();
+        assert_eq!(cpu.memory()[0x300..0x302], [0xAB, 0xCD]);
+    }
+
+    #[test]
+    fn exit_is_reported_as_the_process_exiting() {
+        let mut cpu = build_cpu();
+        cpu.set_platform(Platform::Schip11);
+        cpu.load_program(&[0x60, 0x01, 0x00, 0xFD]);
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            assert_eq!(exchange(&mut stream, "c"), "W00");
+            stream.write_all(b"$k#6b").unwrap();
+        });
+
+        serve_listener(&mut cpu, listener, 10).unwrap();
+        client.join().unwrap();
+        assert!(cpu.halted());
+        assert_eq!(cpu.registers().v[0], 1);
+    }
+
+    #[test]
+    fn megachip_i_is_sent_at_full_width() {
+        let mut cpu = build_cpu();
+        cpu.set_platform(Platform::MegaChip);
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            assert_eq!(exchange(&mut stream, "P10=56341200"), "OK");
+            assert_eq!(exchange(&mut stream, "p10"), "56341200");
+            assert_eq!(&exchange(&mut stream, "g")[32..40], "56341200");
+            stream.write_all(b"$k#6b").unwrap();
+        });
+
+        serve_listener(&mut cpu, listener, 10).unwrap();
+        client.join().unwrap();
+        assert_eq!(cpu.registers().i, 0x123456);
+    }
+
+    #[test]
+    fn packets_with_bad_checksums_are_refused() {
+        let mut cpu = build_cpu();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let bad = "$g#00".repeat(1000);
+            stream.write_all(bad.as_bytes()).unwrap();
+            let mut acks = vec![0u8; 1000];
+            stream.read_exact(&mut acks).unwrap();
+            assert!(acks.iter().all(|&b| b == b'-'));
+            assert_eq!(exchange(&mut stream, "p1"), "00");
+            stream.write_all(b"$k#6b").unwrap();
+        });
+
+        serve_listener(&mut cpu, listener, 10).unwrap();
+        client.join().unwrap();
+    }
+}
@@ -0,0 +1,353 @@
+use crate::platform::Platform;
+
+// A CHIP-8 instruction with its operands extracted. The CPU caches these per
+// address, the disassembler formats them and the assembler encodes them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Instruction {
+    Sys(u16),
+    Cls,
+    Ret,
+    Jp(u16),
+    Call(u16),
+    SeByte { x: u8, nn: u8 },
+    SneByte { x: u8, nn: u8 },
+    SeReg { x: u8, y: u8 },
+    LdByte { x: u8, nn: u8 },
+    AddByte { x: u8, nn: u8 },
+    LdReg { x: u8, y: u8 },
+    Or { x: u8, y: u8 },
+    And { x: u8, y: u8 },
+    Xor { x: u8, y: u8 },
+    AddReg { x: u8, y: u8 },
+    Sub { x: u8, y: u8 },
+    Shr { x: u8, y: u8 },
+    Subn { x: u8, y: u8 },
+    Shl { x: u8, y: u8 },
+    SneReg { x: u8, y: u8 },
+    LdI(u16),
+    JpV0(u16),
+    Rnd { x: u8, nn: u8 },
+    Drw { x: u8, y: u8, n: u8 },
+    Skp(u8),
+    Sknp(u8),
+    LdVxDt(u8),
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddI(u8),
+    LdF(u8),
+    LdB(u8),
+    LdIVx(u8),
+    LdVxI(u8),
+    // CHIP-8X only
+    Bgc,
+    AddNib { x: u8, y: u8 },
+    Col { x: u8, y: u8, n: u8 },
+    Skp2(u8),
+    Sknp2(u8),
+    Out(u8),
+    In(u8),
+    // MegaChip only
+    MegaOff,
+    MegaOn,
+    ScrollUp(u8),
+    // Followed by the low 16 bits of I in the next word
+    LdHi(u8),
+    LdPal(u8),
+    SprW(u8),
+    SprH(u8),
+    Alpha(u8),
+    DigiSnd(u8),
+    StopSnd,
+    BMode(u8),
+    CCol(u8),
+    // SCHIP only; the scrolls arrived in 1.1
+    ScrollDown(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    Low,
+    High,
+    LdHf(u8),
+    LdRVx(u8),
+    LdVxR(u8),
+}
+
+// Decodes the opcodes a platform adds or redefines, and falls back to the
+// common instruction set for the rest
+pub fn decode_for(platform: Platform, opcode: u16) -> Result<Instruction, String> {
+    match platform {
+        Platform::Chip8HiRes if opcode == 0x0230 => return Ok(Instruction::Cls),
+        Platform::MegaChip => return decode_megachip(opcode),
+        Platform::Schip10 | Platform::Schip11 => return decode_schip(platform, opcode),
+        Platform::Chip8X => {}
+        _ => return decode(opcode),
+    }
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+
+    let instruction = match ((opcode & 0xF000) >> 12, x, y, n) {
+        (0x0, 0x2, 0xA, 0x0) => Instruction::Bgc,
+        (0x5, _, _, 0x1) => Instruction::AddNib { x, y },
+        (0xB, _, _, _) => Instruction::Col { x, y, n },
+        (0xE, _, 0xF, 0x2) => Instruction::Skp2(x),
+        (0xE, _, 0xF, 0x5) => Instruction::Sknp2(x),
+        (0xF, _, 0xF, 0x8) => Instruction::Out(x),
+        (0xF, _, 0xF, 0xB) => Instruction::In(x),
+        _ => return decode(opcode),
+    };
+    Ok(instruction)
+}
+
+// MegaChip builds on SCHIP 1.1, so anything it does not add is decoded as
+// SCHIP
+fn decode_megachip(opcode: u16) -> Result<Instruction, String> {
+    let nn = (opcode & 0x00FF) as u8;
+    let n = nn & 0x0F;
+    let instruction = match opcode >> 8 {
+        0x00 if opcode == 0x0010 => Instruction::MegaOff,
+        0x00 if opcode == 0x0011 => Instruction::MegaOn,
+        0x00 if nn & 0xF0 == 0xB0 => Instruction::ScrollUp(n),
+        0x01 => Instruction::LdHi(nn),
+        0x02 => Instruction::LdPal(nn),
+        0x03 => Instruction::SprW(nn),
+        0x04 => Instruction::SprH(nn),
+        0x05 => Instruction::Alpha(nn),
+        0x06 if nn & 0xF0 == 0 => Instruction::DigiSnd(n),
+        0x07 if nn == 0 => Instruction::StopSnd,
+        0x08 if nn <= 5 => Instruction::BMode(n),
+        0x09 => Instruction::CCol(nn),
+        _ => return decode_schip(Platform::MegaChip, opcode),
+    };
+    Ok(instruction)
+}
+
+// The scrolls arrived in SCHIP 1.1, which MegaChip also has
+fn decode_schip(platform: Platform, opcode: u16) -> Result<Instruction, String> {
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let scrolls = platform != Platform::Schip10;
+    let instruction = match opcode & 0xF0FF {
+        0x00C0..=0x00CF if scrolls && x == 0 => Instruction::ScrollDown((opcode & 0xF) as u8),
+        0x00FB if scrolls && x == 0 => Instruction::ScrollRight,
+        0x00FC if scrolls && x == 0 => Instruction::ScrollLeft,
+        0x00FD if x == 0 => Instruction::Exit,
+        0x00FE if x == 0 => Instruction::Low,
+        0x00FF if x == 0 => Instruction::High,
+        0xF030 => Instruction::LdHf(x),
+        0xF075 => Instruction::LdRVx(x),
+        0xF085 => Instruction::LdVxR(x),
+        _ => return decode(opcode),
+    };
+    Ok(instruction)
+}
+
+pub fn decode(opcode: u16) -> Result<Instruction, String> {
+    let nibbles = (
+        ((opcode & 0xF000) >> 12) as u8,
+        ((opcode & 0x0F00) >> 8) as u8,
+        ((opcode & 0x00F0) >> 4) as u8,
+        (opcode & 0x000F) as u8,
+    );
+
+    let nnn = opcode & 0x0FFF;
+    let nn = (opcode & 0x00FF) as u8;
+    let x = nibbles.1;
+    let y = nibbles.2;
+    let n = nibbles.3;
+
+    let instruction = match nibbles {
+        (0x0, 0x0, 0xE, 0x0) => Instruction::Cls,
+        (0x0, 0x0, 0xE, 0xE) => Instruction::Ret,
+        (0x0, _, _, _) => Instruction::Sys(nnn),
+        (0x1, _, _, _) => Instruction::Jp(nnn),
+        (0x2, _, _, _) => Instruction::Call(nnn),
+        (0x3, _, _, _) => Instruction::SeByte { x, nn },
+        (0x4, _, _, _) => Instruction::SneByte { x, nn },
+        (0x5, _, _, 0x0) => Instruction::SeReg { x, y },
+        (0x6, _, _, _) => Instruction::LdByte { x, nn },
+        (0x7, _, _, _) => Instruction::AddByte { x, nn },
+        (0x8, _, _, 0x0) => Instruction::LdReg { x, y },
+        (0x8, _, _, 0x1) => Instruction::Or { x, y },
+        (0x8, _, _, 0x2) => Instruction::And { x, y },
+        (0x8, _, _, 0x3) => Instruction::Xor { x, y },
+        (0x8, _, _, 0x4) => Instruction::AddReg { x, y },
+        (0x8, _, _, 0x5) => Instruction::Sub { x, y },
+        (0x8, _, _, 0x6) => Instruction::Shr { x, y },
+        (0x8, _, _, 0x7) => Instruction::Subn { x, y },
+        (0x8, _, _, 0xE) => Instruction::Shl { x, y },
+        (0x9, _, _, 0x0) => Instruction::SneReg { x, y },
+        (0xA, _, _, _) => Instruction::LdI(nnn),
+        (0xB, _, _, _) => Instruction::JpV0(nnn),
+        (0xC, _, _, _) => Instruction::Rnd { x, nn },
+        (0xD, _, _, _) => Instruction::Drw { x, y, n },
+        (0xE, _, 0x9, 0xE) => Instruction::Skp(x),
+        (0xE, _, 0xA, 0x1) => Instruction::Sknp(x),
+        (0xF, _, 0x0, 0x7) => Instruction::LdVxDt(x),
+        (0xF, _, 0x0, 0xA) => Instruction::LdVxK(x),
+        (0xF, _, 0x1, 0x5) => Instruction::LdDtVx(x),
+        (0xF, _, 0x1, 0x8) => Instruction::LdStVx(x),
+        (0xF, _, 0x1, 0xE) => Instruction::AddI(x),
+        (0xF, _, 0x2, 0x9) => Instruction::LdF(x),
+        (0xF, _, 0x3, 0x3) => Instruction::LdB(x),
+        (0xF, _, 0x5, 0x5) => Instruction::LdIVx(x),
+        (0xF, _, 0x6, 0x5) => Instruction::LdVxI(x),
+        _ => return Err(format!("Invalid opcode {:#06x}", opcode)),
+    };
+    Ok(instruction)
+}
+
+// Operands are masked to their field widths. Encoding is not one-to-one:
+// Sys(0x0E0) and Sys(0x0EE) give the CLS and RET opcodes, other Sys values
+// can land on a platform's own 0NNN instructions, and JpV0 and the CHIP-8X
+// Col share BXYN, so which instruction a word means depends on the platform
+pub fn encode(instruction: Instruction) -> u16 {
+    let xy = |x: u8, y: u8| ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4);
+    let xnn = |x: u8, nn: u8| ((x as u16 & 0xF) << 8) | nn as u16;
+    let fx = |x: u8, low: u16| 0xF000 | ((x as u16 & 0xF) << 8) | low;
+    match instruction {
+        Instruction::Sys(nnn) => nnn & 0x0FFF,
+        Instruction::Cls => 0x00E0,
+        Instruction::Ret => 0x00EE,
+        Instruction::Jp(nnn) => 0x1000 | (nnn & 0x0FFF),
+        Instruction::Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+        Instruction::SeByte { x, nn } => 0x3000 | xnn(x, nn),
+        Instruction::SneByte { x, nn } => 0x4000 | xnn(x, nn),
+        Instruction::SeReg { x, y } => 0x5000 | xy(x, y),
+        Instruction::LdByte { x, nn } => 0x6000 | xnn(x, nn),
+        Instruction::AddByte { x, nn } => 0x7000 | xnn(x, nn),
+        Instruction::LdReg { x, y } => 0x8000 | xy(x, y),
+        Instruction::Or { x, y } => 0x8001 | xy(x, y),
+        Instruction::And { x, y } => 0x8002 | xy(x, y),
+        Instruction::Xor { x, y } => 0x8003 | xy(x, y),
+        Instruction::AddReg { x, y } => 0x8004 | xy(x, y),
+        Instruction::Sub { x, y } => 0x8005 | xy(x, y),
+        Instruction::Shr { x, y } => 0x8006 | xy(x, y),
+        Instruction::Subn { x, y } => 0x8007 | xy(x, y),
+        Instruction::Shl { x, y } => 0x800E | xy(x, y),
+        Instruction::SneReg { x, y } => 0x9000 | xy(x, y),
+        Instruction::LdI(nnn) => 0xA000 | (nnn & 0x0FFF),
+        Instruction::JpV0(nnn) => 0xB000 | (nnn & 0x0FFF),
+        Instruction::Rnd { x, nn } => 0xC000 | xnn(x, nn),
+        Instruction::Drw { x, y, n } => 0xD000 | xy(x, y) | (n as u16 & 0xF),
+        Instruction::Skp(x) => 0xE09E | ((x as u16 & 0xF) << 8),
+        Instruction::Sknp(x) => 0xE0A1 | ((x as u16 & 0xF) << 8),
+        Instruction::LdVxDt(x) => fx(x, 0x07),
+        Instruction::LdVxK(x) => fx(x, 0x0A),
+        Instruction::LdDtVx(x) => fx(x, 0x15),
+        Instruction::LdStVx(x) => fx(x, 0x18),
+        Instruction::AddI(x) => fx(x, 0x1E),
+        Instruction::LdF(x) => fx(x, 0x29),
+        Instruction::LdB(x) => fx(x, 0x33),
+        Instruction::LdIVx(x) => fx(x, 0x55),
+        Instruction::LdVxI(x) => fx(x, 0x65),
+        Instruction::Bgc => 0x02A0,
+        Instruction::AddNib { x, y } => 0x5001 | xy(x, y),
+        Instruction::Col { x, y, n } => 0xB000 | xy(x, y) | (n as u16 & 0xF),
+        Instruction::Skp2(x) => 0xE0F2 | ((x as u16 & 0xF) << 8),
+        Instruction::Sknp2(x) => 0xE0F5 | ((x as u16 & 0xF) << 8),
+        Instruction::Out(x) => fx(x, 0xF8),
+        Instruction::In(x) => fx(x, 0xFB),
+        Instruction::MegaOff => 0x0010,
+        Instruction::MegaOn => 0x0011,
+        Instruction::ScrollUp(n) => 0x00B0 | (n as u16 & 0xF),
+        Instruction::LdHi(nn) => 0x0100 | nn as u16,
+        Instruction::LdPal(nn) => 0x0200 | nn as u16,
+        Instruction::SprW(nn) => 0x0300 | nn as u16,
+        Instruction::SprH(nn) => 0x0400 | nn as u16,
+        Instruction::Alpha(nn) => 0x0500 | nn as u16,
+        Instruction::DigiSnd(n) => 0x0600 | (n as u16 & 0xF),
+        Instruction::StopSnd => 0x0700,
+        Instruction::BMode(n) => 0x0800 | (n as u16 & 0xF),
+        Instruction::CCol(nn) => 0x0900 | nn as u16,
+        Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+        Instruction::ScrollRight => 0x00FB,
+        Instruction::ScrollLeft => 0x00FC,
+        Instruction::Exit => 0x00FD,
+        Instruction::Low => 0x00FE,
+        Instruction::High => 0x00FF,
+        Instruction::LdHf(x) => fx(x, 0x30),
+        Instruction::LdRVx(x) => fx(x, 0x75),
+        Instruction::LdVxR(x) => fx(x, 0x85),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLATFORMS: [Platform; 6] = [
+        Platform::Chip8,
+        Platform::Chip8X,
+        Platform::Chip8HiRes,
+        Platform::MegaChip,
+        Platform::Schip10,
+        Platform::Schip11,
+    ];
+
+    #[test]
+    fn every_decoded_opcode_encodes_back_to_itself() {
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = decode(opcode) {
+                assert_eq!(encode(instruction), opcode, "{:?}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn every_platform_opcode_encodes_back_to_itself() {
+        for platform in PLATFORMS {
+            for opcode in 0..=0xFFFF {
+                let Ok(instruction) = decode_for(platform, opcode) else {
+                    continue;
+                };
+                // The hi-res interpreter's own clear is the only alias
+                if platform == Platform::Chip8HiRes && opcode == 0x0230 {
+                    assert_eq!(instruction, Instruction::Cls);
+                    continue;
+                }
+                assert_eq!(
+                    encode(instruction),
+                    opcode,
+                    "{:?} on {}",
+                    instruction,
+                    platform.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shared_encodings_decode_by_platform() {
+        assert_eq!(encode(Instruction::Sys(0x0E0)), encode(Instruction::Cls));
+        assert_eq!(encode(Instruction::Sys(0x0EE)), encode(Instruction::Ret));
+        assert_eq!(
+            decode(encode(Instruction::Sys(0x0E0))),
+            Ok(Instruction::Cls)
+        );
+        assert_eq!(
+            decode(encode(Instruction::Sys(0x0EE))),
+            Ok(Instruction::Ret)
+        );
+
+        let jump = Instruction::JpV0(0x123);
+        let color = Instruction::Col { x: 1, y: 2, n: 3 };
+        assert_eq!(encode(jump), 0xB123);
+        assert_eq!(encode(color), 0xB123);
+        assert_eq!(decode_for(Platform::Chip8, 0xB123), Ok(jump));
+        assert_eq!(decode_for(Platform::Chip8X, 0xB123), Ok(color));
+    }
+
+    #[test]
+    fn megachip_adds_to_schip() {
+        let decode = |opcode| decode_for(Platform::MegaChip, opcode);
+        assert_eq!(decode(0x0805), Ok(Instruction::BMode(5)));
+        // Other blend modes are left to SYS, which faults
+        assert_eq!(decode(0x0806), Ok(Instruction::Sys(0x806)));
+        assert_eq!(decode(0x080F), Ok(Instruction::Sys(0x80F)));
+        assert_eq!(decode(0x00C3), Ok(Instruction::ScrollDown(3)));
+        assert_eq!(decode(0x00FF), Ok(Instruction::High));
+        assert_eq!(decode(0xF130), Ok(Instruction::LdHf(1)));
+        assert_eq!(decode(0x00E0), Ok(Instruction::Cls));
+    }
+}
@@ -0,0 +1,27 @@
+pub mod assembler;
+pub mod bench;
+pub mod blocks;
+pub mod callstack;
+pub mod capture;
+pub mod cartridge;
+pub mod config;
+pub mod coverage;
+pub mod cpu;
+pub mod crt;
+pub mod dap;
+pub mod disassembler;
+pub mod display;
+pub mod framelog;
+pub mod gdb;
+pub mod instruction;
+pub mod loader;
+pub mod megachip;
+pub mod monitor;
+pub mod palette;
+pub mod phosphor;
+pub mod platform;
+pub mod profiler;
+pub mod quirks;
+pub mod scaling;
+pub mod symbols;
+pub mod trace;
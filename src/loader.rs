@@ -0,0 +1,58 @@
+use crate::assembler;
+use crate::cartridge::{self, Options};
+use crate::cpu::Cpu;
+use crate::symbols::{Symbols, build_symbols, load_symbols, symbols_location};
+
+pub struct Loaded {
+    pub options: Option<Options>,
+    pub symbols: Option<Symbols>,
+}
+
+// Loads an Octo cartridge (.gif), Octo source (.8o) or raw ROM into memory.
+// Source files produce their own symbols; a ROM picks up a symbol file of
+// the same name if there is one.
+pub fn load_program(cpu: &mut Cpu, path: &str) -> Result<Loaded, String> {
+    let platform = cpu.platform();
+    if (path.ends_with(".gif") || path.ends_with(".8o"))
+        && platform.load_address() != assembler::PROGRAM_START
+    {
+        return Err(format!(
+            "Octo programs start at 0x200, but {} programs start at {:#05x}",
+            platform.name(),
+            platform.load_address()
+        ));
+    }
+
+    if path.ends_with(".gif") {
+        let cartridge = cartridge::load_cartridge(path)?;
+        cpu.load_program(&cartridge.program);
+        return Ok(Loaded {
+            options: Some(cartridge.options),
+            symbols: None,
+        });
+    }
+
+    if path.ends_with(".8o") {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read source file at '{}': {}", path, e))?;
+        let program = assembler::assemble(&source)
+            .map_err(|e| format!("Failed to assemble '{}': {}", path, e))?;
+        cpu.load_program(&program.bytes);
+        return Ok(Loaded {
+            options: None,
+            symbols: Some(build_symbols(&program, Some(path))),
+        });
+    }
+
+    cpu.load_rom(path)?;
+    let location = symbols_location(path);
+    let symbols = if std::path::Path::new(&location).exists() {
+        Some(load_symbols(&location)?)
+    } else {
+        None
+    };
+    Ok(Loaded {
+        options: None,
+        symbols,
+    })
+}
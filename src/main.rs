@@ -1,20 +1,20 @@
-mod cpu;
-mod display;
-
 use pixels::{Pixels, SurfaceTexture};
+use rust_chip8::{
+    assembler, bench, capture, cartridge, config, coverage, cpu, crt, dap, display, framelog, gdb,
+    loader, monitor, palette, phosphor, profiler, scaling, symbols, trace,
+};
+use std::rc::Rc;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use winit::application::ApplicationHandler;
 use winit::event::{ElementState, KeyEvent, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, EventLoop};
 use winit::keyboard::{KeyCode, PhysicalKey};
-use winit::window::{Window, WindowId};
+use winit::window::{Fullscreen, Window, WindowId};
 
 const ROM_PATH: &str = "../roms/chip8.ch8";
 const SHOW_FPS: bool = true;
 
-const DISPLAY_WIDTH: u32 = 64;
-const DISPLAY_HEIGHT: u32 = 32;
 const SCALE: u32 = 10;
 
 const TARGET_FPS: f64 = 60.0;
@@ -22,13 +22,37 @@ const FRAME_TIME: Duration = Duration::from_nanos((1_000_000_000.0 / TARGET_FPS)
 const TIMER_INTERVAL: Duration = Duration::from_micros(16667);
 const CYCLES_PER_FRAME: usize = 12;
 
-const COLOR_ON: [u8; 4] = [0, 255, 159, 255];
-const COLOR_OFF: [u8; 4] = [10, 14, 39, 255];
-
 struct App<'a> {
     window: Option<Arc<Window>>,
     pixels: Option<Pixels<'a>>,
     cpu: cpu::Cpu,
+    cycles_per_frame: usize,
+    palettes: Vec<palette::Palette>,
+    palette_index: usize,
+    phosphor: phosphor::Phosphor,
+    effects: crt::Effects,
+    // The screen currently shown; sync_display_size follows MegaChip
+    // programs as they switch between the SCHIP and MegaChip screens
+    display_size: (usize, usize),
+    base_frame: Vec<u8>,
+    // Part of base_frame that no longer matches the phosphor state
+    stale_region: Option<display::Rect>,
+    effect_frame: Vec<u8>,
+    scale_mode: scaling::ScaleMode,
+    surface_size: (usize, usize),
+    start_fullscreen: bool,
+    recorder: Option<capture::Recorder>,
+    capture_scale: usize,
+    screenshot_path: Option<String>,
+    frame_log: Option<framelog::FrameLogger>,
+    profile_path: Option<String>,
+    profile_folded_path: Option<String>,
+    coverage_path: Option<String>,
+    coverage_map_path: Option<String>,
+    monitor: Option<monitor::Monitor>,
+    symbols: Option<Rc<symbols::Symbols>>,
+    paused: bool,
+    step_out_depth: Option<usize>,
     last_timer_update: Instant,
     last_frame_time: Instant,
     frame_count: u32,
@@ -36,19 +60,79 @@ struct App<'a> {
 }
 
 impl<'a> App<'a> {
-    fn new(cpu: cpu::Cpu) -> Self {
+    fn new(cpu: cpu::Cpu, palettes: Vec<palette::Palette>, config: &config::Config) -> Self {
         let now = Instant::now();
+        let (width, height) = cpu.display_size();
         Self {
             window: None,
             pixels: None,
             cpu,
+            cycles_per_frame: CYCLES_PER_FRAME,
+            palettes,
+            palette_index: 0,
+            phosphor: phosphor::build_phosphor(config.ghosting),
+            effects: config.crt,
+            display_size: (width, height),
+            base_frame: vec![0; width * height * 4],
+            stale_region: Some(display::screen_rect(width, height)),
+            effect_frame: vec![0; width * height * 4 * crt::EFFECT_SCALE * crt::EFFECT_SCALE],
+            scale_mode: config.scale_mode,
+            surface_size: (0, 0),
+            start_fullscreen: config.fullscreen,
+            recorder: None,
+            capture_scale: config.capture_scale,
+            screenshot_path: config.screenshot.clone(),
+            frame_log: None,
+            profile_path: config.profile.clone(),
+            profile_folded_path: config.profile_folded.clone(),
+            coverage_path: config.coverage.clone(),
+            coverage_map_path: config.coverage_map.clone(),
+            monitor: None,
+            symbols: None,
+            paused: false,
+            step_out_depth: None,
             last_timer_update: now,
             last_frame_time: now,
             frame_count: 0,
             last_fps_update: now,
         }
     }
+
+    fn apply_options(&mut self, options: cartridge::Options) {
+        self.cpu.set_quirks(options.quirks);
+        if let Some(tickrate) = options.tickrate {
+            self.cycles_per_frame = tickrate;
+        }
+        if let Some(palette) = options.palette {
+            self.palettes.insert(0, palette);
+            self.palette_index = 0;
+        }
+    }
+
+    fn select_palette(&mut self, name: &str) {
+        match self.palettes.iter().position(|p| p.name == name) {
+            Some(index) => self.palette_index = index,
+            None => eprintln!("Unknown palette '{}'", name),
+        }
+    }
+
+    fn cycle_palette(&mut self) {
+        self.palette_index = (self.palette_index + 1) % self.palettes.len();
+        self.stale_region = Some(display::screen_rect(
+            self.display_size.0,
+            self.display_size.1,
+        ));
+        println!("Palette: {}", self.palettes[self.palette_index].name);
+    }
+
     fn handle_keyboard(&mut self, key_event: KeyEvent) {
+        if let PhysicalKey::Code(code) = key_event.physical_key
+            && key_event.state == ElementState::Pressed
+            && !key_event.repeat
+        {
+            self.handle_hotkey(code);
+        }
+
         let chip8_key = Self::map_key_to_chip8(key_event.physical_key);
 
         if let Some(key) = chip8_key {
@@ -57,6 +141,246 @@ impl<'a> App<'a> {
                 ElementState::Released => self.cpu.key_release(key),
             }
         }
+
+        if let Some(key) = Self::map_key_to_second_keypad(key_event.physical_key) {
+            match key_event.state {
+                ElementState::Pressed => self.cpu.second_key_press(key),
+                ElementState::Released => self.cpu.second_key_release(key),
+            }
+        }
+    }
+
+    fn cycle_ghosting(&mut self) {
+        let (mode, name) = match self.phosphor.mode() {
+            phosphor::Persistence::Off => (
+                phosphor::Persistence::Decay(phosphor::DEFAULT_DECAY),
+                "decay",
+            ),
+            phosphor::Persistence::Decay(_) => (phosphor::Persistence::MaxOfTwo, "max2"),
+            phosphor::Persistence::MaxOfTwo => (phosphor::Persistence::Off, "off"),
+        };
+        self.phosphor.set_mode(mode);
+        println!("Ghosting: {}", name);
+    }
+
+    fn cycle_scale_mode(&mut self) {
+        self.scale_mode = self.scale_mode.next();
+        self.resize_buffer();
+        println!("Scale mode: {}", self.scale_mode.name());
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        if let Some(window) = &self.window {
+            let fullscreen = match window.fullscreen() {
+                Some(_) => None,
+                None => Some(Fullscreen::Borderless(None)),
+            };
+            window.set_fullscreen(fullscreen);
+        }
+    }
+
+    // Brings base_frame up to date and returns whether anything was redrawn
+    fn update_base_frame(&mut self) -> bool {
+        let Some(region) = self.stale_region.take() else {
+            return false;
+        };
+        // MegaChip mode is drawn in full color by the CPU, without ghosting
+        if let Some(mega) = self.cpu.mega_screen() {
+            self.base_frame.copy_from_slice(mega.frame());
+            return true;
+        }
+        let palette = &self.palettes[self.palette_index];
+        let colors = self.cpu.colors();
+        phosphor::render_region(
+            &self.phosphor,
+            &mut self.base_frame,
+            palette,
+            colors,
+            region,
+        );
+        true
+    }
+
+    fn save_screenshot(&mut self, location: &str) {
+        self.update_base_frame();
+        match capture::save_png(
+            location,
+            &self.base_frame,
+            self.display_size,
+            self.capture_scale,
+        ) {
+            Ok(()) => println!("Saved screenshot to {}", location),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn start_recording(&mut self, location: &str) {
+        match capture::start_recording(location, self.display_size, self.capture_scale) {
+            Ok(recorder) => {
+                println!("Recording to {}", location);
+                self.recorder = Some(recorder);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(()) => println!("Recording saved"),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        if self.recorder.is_some() {
+            self.stop_recording();
+        } else {
+            self.start_recording(&format!("recording-{}.gif", timestamp()));
+        }
+    }
+
+    fn record_frame(&mut self) {
+        if self.recorder.is_none() {
+            return;
+        }
+        self.update_base_frame();
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(e) = recorder.add_frame(&self.base_frame) {
+            eprintln!("{}", e);
+            self.recorder = None;
+        }
+    }
+
+    fn step_frame(&mut self) {
+        if self.frame_log.is_none() && self.step_out_depth.is_none() {
+            self.cpu.run(self.cycles_per_frame);
+            return;
+        }
+        for _ in 0..self.cycles_per_frame {
+            if let Some(log) = &mut self.frame_log
+                && !self.cpu.is_waiting_for_display()
+            {
+                log.record_instruction(self.cpu.pc(), self.cpu.fetch_instruction());
+            }
+            self.cpu.cycle();
+            if let Some(depth) = self.step_out_depth
+                && self.cpu.call_stack().len() < depth
+            {
+                self.toggle_pause();
+                break;
+            }
+        }
+    }
+
+    fn finish_frame(&mut self) {
+        self.cpu.end_frame();
+        for warning in self.cpu.take_stack_warnings() {
+            eprintln!("Warning: {}", warning);
+        }
+        self.sync_display_size();
+        let display = self.cpu.get_display();
+        let dirty = self.cpu.take_display_changes();
+        let changed = self.phosphor.update(&display, dirty);
+        // Recoloring a zone, or fading the MegaChip screen, changes how it
+        // looks without changing its pixels
+        let recolored =
+            dirty.filter(|_| self.cpu.colors().is_some() || self.cpu.mega_screen().is_some());
+        self.stale_region = display::merge(display::merge(self.stale_region, changed), recolored);
+        self.record_frame();
+
+        if let Some(log) = &mut self.frame_log
+            && let Err(e) = log.record_frame(&display)
+        {
+            eprintln!("{}", e);
+            self.frame_log = None;
+        }
+    }
+
+    // MegaChip programs switch between the CHIP-8 and MegaChip screens
+    fn sync_display_size(&mut self) {
+        let (width, height) = self.cpu.display_size();
+        if (width, height) == self.display_size {
+            return;
+        }
+        self.display_size = (width, height);
+        self.base_frame = vec![0; width * height * 4];
+        self.effect_frame = vec![0; width * height * 4 * crt::EFFECT_SCALE * crt::EFFECT_SCALE];
+        self.stale_region = Some(display::screen_rect(width, height));
+        self.resize_buffer();
+        if self.recorder.is_some() {
+            eprintln!("Screen size changed to {}x{}", width, height);
+            self.stop_recording();
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(location) = self.screenshot_path.take() {
+            self.save_screenshot(&location);
+        }
+        self.stop_recording();
+        if let Some(log) = self.frame_log.take()
+            && let Err(e) = log.finish()
+        {
+            eprintln!("{}", e);
+        }
+        if let Some(mut tracer) = self.cpu.take_tracer() {
+            tracer.flush();
+        }
+        if let Some(profiler) = self.cpu.take_profiler() {
+            profiler::save_reports(
+                &profiler,
+                self.cpu.platform(),
+                self.symbols.as_deref(),
+                self.profile_path.as_deref(),
+                self.profile_folded_path.as_deref(),
+            );
+        }
+        if let Some(coverage) = self.cpu.take_coverage() {
+            coverage::save_reports(
+                &coverage,
+                self.cpu.memory(),
+                self.cpu.platform(),
+                self.symbols.as_deref(),
+                self.coverage_path.as_deref(),
+                self.coverage_map_path.as_deref(),
+                self.capture_scale,
+            );
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume();
+            return;
+        }
+        self.paused = true;
+        self.step_out_depth = None;
+        self.monitor
+            .get_or_insert_with(|| monitor::start_monitor(self.symbols.clone()))
+            .pause(&self.cpu);
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+        self.last_timer_update = Instant::now();
+        println!("Resumed");
+    }
+
+    fn handle_hotkey(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::F2 => self.cycle_palette(),
+            KeyCode::F3 => self.cycle_ghosting(),
+            KeyCode::F4 => self.cycle_scale_mode(),
+            KeyCode::F5 => self.toggle_pause(),
+            KeyCode::F9 => self.toggle_recording(),
+            KeyCode::F12 => self.save_screenshot(&format!("screenshot-{}.png", timestamp())),
+            KeyCode::F11 => self.toggle_fullscreen(),
+            _ => {}
+        }
     }
 
     fn map_key_to_chip8(physical_key: PhysicalKey) -> Option<u8> {
@@ -86,6 +410,33 @@ impl<'a> App<'a> {
         }
     }
 
+    fn map_key_to_second_keypad(physical_key: PhysicalKey) -> Option<u8> {
+        // CHIP-8X second keypad, same layout one block to the right:
+        // 7 8 9 0
+        // U I O P
+        // J K L ;
+        // M , . /
+        match physical_key {
+            PhysicalKey::Code(KeyCode::Digit7) => Some(0x1),
+            PhysicalKey::Code(KeyCode::Digit8) => Some(0x2),
+            PhysicalKey::Code(KeyCode::Digit9) => Some(0x3),
+            PhysicalKey::Code(KeyCode::Digit0) => Some(0xC),
+            PhysicalKey::Code(KeyCode::KeyU) => Some(0x4),
+            PhysicalKey::Code(KeyCode::KeyI) => Some(0x5),
+            PhysicalKey::Code(KeyCode::KeyO) => Some(0x6),
+            PhysicalKey::Code(KeyCode::KeyP) => Some(0xD),
+            PhysicalKey::Code(KeyCode::KeyJ) => Some(0x7),
+            PhysicalKey::Code(KeyCode::KeyK) => Some(0x8),
+            PhysicalKey::Code(KeyCode::KeyL) => Some(0x9),
+            PhysicalKey::Code(KeyCode::Semicolon) => Some(0xE),
+            PhysicalKey::Code(KeyCode::KeyM) => Some(0xA),
+            PhysicalKey::Code(KeyCode::Comma) => Some(0x0),
+            PhysicalKey::Code(KeyCode::Period) => Some(0xB),
+            PhysicalKey::Code(KeyCode::Slash) => Some(0xF),
+            _ => None,
+        }
+    }
+
     fn update_timers(&mut self) {
         let now = Instant::now();
         if now.duration_since(self.last_timer_update) >= TIMER_INTERVAL {
@@ -108,24 +459,103 @@ impl<'a> App<'a> {
         }
     }
 
+    fn image_size(&self) -> (usize, usize) {
+        let scale = if self.effects.any() {
+            crt::EFFECT_SCALE
+        } else {
+            1
+        };
+        (self.display_size.0 * scale, self.display_size.1 * scale)
+    }
+
+    // Integer scaling is left to the GPU; the other modes are drawn into a
+    // buffer matching the window so they are not snapped to whole multiples
+    fn buffer_size(&self) -> (u32, u32) {
+        let (width, height) = match self.scale_mode {
+            scaling::ScaleMode::Integer => self.image_size(),
+            _ => self.surface_size,
+        };
+        (width as u32, height as u32)
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.surface_size = (width as usize, height as usize);
+        if let Some(pixels) = &mut self.pixels
+            && pixels.resize_surface(width, height).is_err()
+        {
+            eprintln!("Failed to resize surface");
+        }
+        self.resize_buffer();
+    }
+
+    fn resize_buffer(&mut self) {
+        let (width, height) = self.buffer_size();
+        if let Some(pixels) = &mut self.pixels
+            && pixels.resize_buffer(width, height).is_err()
+        {
+            eprintln!("Failed to resize pixel buffer");
+        }
+    }
+
     fn render(&mut self) {
-        if let Some(pixels) = &mut self.pixels {
-            render_display(&self.cpu, pixels.frame_mut());
-            if pixels.render().is_err() {
-                eprintln!("Failed to render frame");
+        let image_size = self.image_size();
+        if self.pixels.is_none() {
+            return;
+        }
+        let redrawn = self.update_base_frame();
+        let Some(pixels) = &mut self.pixels else {
+            return;
+        };
+        let palette = &self.palettes[self.palette_index];
+
+        // The CRT pass is the expensive part, so it only reruns on changes
+        let mut image = &self.base_frame;
+        if self.effects.any() {
+            if redrawn {
+                crt::apply(
+                    &self.effects,
+                    &self.base_frame,
+                    self.display_size.0,
+                    self.display_size.1,
+                    &mut self.effect_frame,
+                );
             }
+            image = &self.effect_frame;
+        }
+
+        if self.scale_mode == scaling::ScaleMode::Integer {
+            pixels.frame_mut().copy_from_slice(image);
+        } else {
+            scaling::blit(
+                self.scale_mode,
+                image,
+                image_size,
+                pixels.frame_mut(),
+                self.surface_size,
+                palette.color(0),
+            );
+        }
+
+        if pixels.render().is_err() {
+            eprintln!("Failed to render frame");
         }
     }
 }
 
 impl<'a> ApplicationHandler for App<'a> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let (width, height) = (self.display_size.0 as u32, self.display_size.1 as u32);
         let window_attributes = Window::default_attributes()
             .with_title("CHIP-8 Emulator")
-            .with_inner_size(winit::dpi::LogicalSize::new(
-                DISPLAY_WIDTH * SCALE,
-                DISPLAY_HEIGHT * SCALE,
-            ));
+            .with_inner_size(winit::dpi::LogicalSize::new(width * SCALE, height * SCALE))
+            .with_min_inner_size(winit::dpi::LogicalSize::new(width, height))
+            .with_fullscreen(
+                self.start_fullscreen
+                    .then_some(Fullscreen::Borderless(None)),
+            );
 
         let window = Arc::new(
             event_loop
@@ -134,9 +564,11 @@ impl<'a> ApplicationHandler for App<'a> {
         );
 
         let window_size = window.inner_size();
+        self.surface_size = (window_size.width as usize, window_size.height as usize);
         let surface_texture =
             SurfaceTexture::new(window_size.width, window_size.height, window.clone());
-        let pixels = Pixels::new(DISPLAY_WIDTH, DISPLAY_HEIGHT, surface_texture)
+        let (buffer_width, buffer_height) = self.buffer_size();
+        let pixels = Pixels::new(buffer_width, buffer_height, surface_texture)
             .expect("Failed to create pixel buffer");
 
         self.window = Some(window);
@@ -149,11 +581,16 @@ impl<'a> ApplicationHandler for App<'a> {
             WindowEvent::CloseRequested => event_loop.exit(),
             WindowEvent::KeyboardInput { event, .. } => self.handle_keyboard(event),
             WindowEvent::RedrawRequested => self.render(),
+            WindowEvent::Resized(size) => self.resize(size.width, size.height),
             _ => {}
         }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        self.shutdown();
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         let now = Instant::now();
         let elapsed = now.duration_since(self.last_frame_time);
         if elapsed < FRAME_TIME {
@@ -161,13 +598,27 @@ impl<'a> ApplicationHandler for App<'a> {
         }
         self.last_frame_time = Instant::now();
 
-        for _ in 0..CYCLES_PER_FRAME {
-            self.cpu.cycle();
+        if let Some(monitor) = &mut self.monitor {
+            match monitor.poll(&mut self.cpu, self.paused) {
+                Some(monitor::Request::Continue) if self.paused => self.resume(),
+                Some(monitor::Request::StepOut) => {
+                    self.step_out_depth = Some(self.cpu.call_stack().len());
+                    self.resume();
+                }
+                _ => {}
+            }
         }
 
-        self.update_timers();
-        self.cpu.end_frame();
-        self.update_fps();
+        if !self.paused {
+            self.step_frame();
+            self.update_timers();
+            self.finish_frame();
+            self.update_fps();
+            if self.cpu.halted() {
+                println!("Program exited at {:03X}", self.cpu.pc());
+                event_loop.exit();
+            }
+        }
 
         if let Some(window) = &self.window {
             window.request_redraw();
@@ -176,27 +627,109 @@ impl<'a> ApplicationHandler for App<'a> {
 }
 
 fn main() {
-    let mut cpu = cpu::build_cpu();
-    cpu.load_rom(ROM_PATH);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "diff-frames") {
+        std::process::exit(framelog::diff_command(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "assemble") {
+        std::process::exit(assembler::assemble_command(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "bench") {
+        std::process::exit(bench::bench_command(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "dap") {
+        std::process::exit(dap::dap_command());
+    }
 
-    let event_loop = EventLoop::new().expect("Failed to create event loop");
+    let mut config = config::parse_args(args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let rom_path = config.rom_path.as_deref().unwrap_or(ROM_PATH);
 
-    let mut app = App::new(cpu);
+    let mut cpu = cpu::build_cpu();
+    cpu.set_platform(config.platform);
+    let loaded = loader::load_program(&mut cpu, rom_path).unwrap_or_else(|e| panic!("{}", e));
+    let symbols = match &config.symbols {
+        Some(location) => Some(symbols::load_symbols(location).unwrap_or_else(|e| panic!("{}", e))),
+        None => loaded.symbols,
+    }
+    .map(Rc::new);
+    if let Some(trace_options) = config.trace.take() {
+        let mut tracer = trace::create_tracer(trace_options).unwrap_or_else(|e| panic!("{}", e));
+        if let Some(symbols) = &symbols {
+            tracer.set_symbols(symbols.clone());
+        }
+        tracer.set_platform(config.platform);
+        cpu.attach_tracer(tracer);
+    }
+    if config.profile.is_some() || config.profile_folded.is_some() {
+        cpu.attach_profiler(profiler::build_profiler());
+    }
+    if config.coverage.is_some() || config.coverage_map.is_some() {
+        cpu.attach_coverage(coverage::build_coverage());
+    }
+    cpu.set_block_cache(config.block_cache);
 
-    event_loop.run_app(&mut app).expect("Event loop error");
-}
+    let mut palettes = palette::builtin_palettes();
+    if let Some(colors) = &config.colors {
+        let custom = palette::parse_palette("custom", colors).unwrap_or_else(|e| panic!("{}", e));
+        palettes.insert(0, custom);
+    }
 
-fn render_display(cpu: &cpu::Cpu, frame: &mut [u8]) {
-    let display = cpu.get_display();
+    let mut app = App::new(cpu, palettes, &config);
+    app.symbols = symbols;
+    if let Some(mut options) = loaded.options {
+        // A palette given on the command line wins over the cartridge's
+        if config.colors.is_some() || config.palette.is_some() {
+            options.palette = None;
+        }
+        app.apply_options(options);
+    }
+    if let Some(name) = &config.palette {
+        app.select_palette(name);
+    }
+    if let Some(location) = &config.record {
+        app.start_recording(location);
+    }
+    if let Some(location) = &config.frame_log {
+        let log = framelog::create_frame_log(location).unwrap_or_else(|e| panic!("{}", e));
+        app.frame_log = Some(log);
+    }
 
-    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-        let x = i % 64;
-        let y = i / 64;
+    if let Some(port) = config.gdb {
+        if let Err(e) = gdb::serve(&mut app.cpu, port, app.cycles_per_frame) {
+            eprintln!("{}", e);
+        }
+        app.shutdown();
+        return;
+    }
 
-        let is_on = display[y][x] != 0;
+    if config.headless {
+        run_headless(&mut app, config.frames);
+        return;
+    }
 
-        let color = if is_on { COLOR_ON } else { COLOR_OFF };
+    let event_loop = EventLoop::new().expect("Failed to create event loop");
+    event_loop.run_app(&mut app).expect("Event loop error");
+}
 
-        pixel.copy_from_slice(&color);
+// Runs as fast as possible with one timer tick per frame, so results are
+// reproducible regardless of host speed. Stops early if the program exits.
+fn run_headless(app: &mut App, frames: usize) {
+    for _ in 0..frames {
+        app.step_frame();
+        app.cpu.decrement_timers();
+        app.finish_frame();
+        if app.cpu.halted() {
+            break;
+        }
     }
+    app.shutdown();
+}
+
+fn timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
 }
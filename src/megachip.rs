@@ -0,0 +1,269 @@
+use crate::display::{Rect, Snapshot, build_snapshot, screen_rect};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 192;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Blend {
+    Normal,
+    // Sprites drawn 25%, 50% or 75% transparent
+    Transparent25,
+    Transparent50,
+    Transparent75,
+    Add,
+    Multiply,
+}
+
+pub fn blend_mode(mode: u8) -> Option<Blend> {
+    match mode {
+        0 => Some(Blend::Normal),
+        1 => Some(Blend::Transparent25),
+        2 => Some(Blend::Transparent50),
+        3 => Some(Blend::Transparent75),
+        4 => Some(Blend::Add),
+        5 => Some(Blend::Multiply),
+        _ => None,
+    }
+}
+
+// A digitised sound started by 060N. The header at I holds the sample rate
+// in two bytes and the length in three, followed by a reserved byte and
+// unsigned 8-bit samples.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sample {
+    pub address: usize,
+    pub rate: u16,
+    pub length: usize,
+    pub looping: bool,
+}
+
+pub fn read_sample(memory: &[u8], address: usize, looping: bool) -> Sample {
+    let byte = |offset: usize| memory[(address + offset) % memory.len()] as usize;
+    Sample {
+        address: (address + 6) % memory.len(),
+        rate: (byte(0) << 8 | byte(1)) as u16,
+        length: byte(2) << 16 | byte(3) << 8 | byte(4),
+        looping,
+    }
+}
+
+// MegaChip mode draws sprites of one palette index per byte into a back
+// buffer, blending them with what is already there. 00E0 shows the back
+// buffer and clears it, so programs redraw the whole screen every frame.
+pub struct MegaScreen {
+    enabled: bool,
+    palette: [[u8; 4]; 256],
+    back: Vec<[u8; 4]>,
+    back_indices: Vec<u8>,
+    front: Vec<u8>,
+    front_indices: Vec<u8>,
+    sprite_width: usize,
+    sprite_height: usize,
+    alpha: u8,
+    blend: Blend,
+    collision_color: u8,
+    dirty: Option<Rect>,
+}
+
+pub fn build_mega_screen() -> MegaScreen {
+    MegaScreen {
+        enabled: false,
+        palette: [[0, 0, 0, 255]; 256],
+        back: vec![[0, 0, 0, 255]; WIDTH * HEIGHT],
+        back_indices: vec![0; WIDTH * HEIGHT],
+        front: vec![0; WIDTH * HEIGHT * 4],
+        front_indices: vec![0; WIDTH * HEIGHT],
+        sprite_width: 0,
+        sprite_height: 0,
+        alpha: 255,
+        blend: Blend::Normal,
+        collision_color: 0,
+        dirty: None,
+    }
+}
+
+impl MegaScreen {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.dirty = Some(screen_rect(WIDTH, HEIGHT));
+    }
+
+    // Colors are stored as ARGB and fill the palette from index 1, since
+    // index 0 is transparent
+    pub fn load_palette(&mut self, memory: &[u8], address: usize, count: usize) {
+        for index in 0..count {
+            let byte = |offset: usize| memory[(address + index * 4 + offset) % memory.len()];
+            if let Some(color) = self.palette.get_mut(index + 1) {
+                *color = [byte(1), byte(2), byte(3), byte(0)];
+            }
+        }
+    }
+
+    // A size of 0 stands for 256
+    pub fn set_sprite_width(&mut self, width: u8) {
+        self.sprite_width = if width == 0 { 256 } else { width as usize };
+    }
+
+    pub fn set_sprite_height(&mut self, height: u8) {
+        self.sprite_height = if height == 0 { 256 } else { height as usize };
+    }
+
+    pub fn set_alpha(&mut self, alpha: u8) {
+        self.alpha = alpha;
+    }
+
+    pub fn set_blend(&mut self, blend: Blend) {
+        self.blend = blend;
+    }
+
+    pub fn set_collision_color(&mut self, index: u8) {
+        self.collision_color = index;
+    }
+
+    // Sprites are clipped at the edges. A collision is drawing over a pixel
+    // of the collision color.
+    pub fn draw_sprite(&mut self, memory: &[u8], address: usize, x: usize, y: usize) -> bool {
+        let mut collision = false;
+        for row in 0..self.sprite_height {
+            for column in 0..self.sprite_width {
+                let offset = address + row * self.sprite_width + column;
+                let index = memory[offset % memory.len()];
+                let (x, y) = (x + column, y + row);
+                if index == 0 || x >= WIDTH || y >= HEIGHT {
+                    continue;
+                }
+                let pixel = y * WIDTH + x;
+                if self.back_indices[pixel] == self.collision_color {
+                    collision = true;
+                }
+                self.back_indices[pixel] = index;
+                self.back[pixel] = self.blend(self.back[pixel], self.palette[index as usize]);
+            }
+        }
+        collision
+    }
+
+    fn blend(&self, below: [u8; 4], color: [u8; 4]) -> [u8; 4] {
+        let opacity = color[3] as f32 / 255.0
+            * match self.blend {
+                Blend::Transparent25 => 0.75,
+                Blend::Transparent50 => 0.5,
+                Blend::Transparent75 => 0.25,
+                _ => 1.0,
+            };
+        let mut blended = below;
+        for channel in 0..3 {
+            let (from, to) = (below[channel] as f32, color[channel] as f32);
+            blended[channel] = match self.blend {
+                Blend::Add => (from + to).min(255.0) as u8,
+                Blend::Multiply => (from * to / 255.0) as u8,
+                _ => (from + (to - from) * opacity).round() as u8,
+            };
+        }
+        blended
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        let rows = rows.min(HEIGHT);
+        self.back.copy_within(rows * WIDTH.., 0);
+        self.back[(HEIGHT - rows) * WIDTH..].fill([0, 0, 0, 255]);
+        self.back_indices.copy_within(rows * WIDTH.., 0);
+        self.back_indices[(HEIGHT - rows) * WIDTH..].fill(0);
+    }
+
+    // Shows the back buffer faded by the screen alpha, then clears it
+    pub fn present(&mut self) {
+        let fade = self.alpha as f32 / 255.0;
+        for (pixel, color) in self.front.chunks_exact_mut(4).zip(&self.back) {
+            for channel in 0..3 {
+                pixel[channel] = (color[channel] as f32 * fade).round() as u8;
+            }
+            pixel[3] = 255;
+        }
+        self.front_indices.copy_from_slice(&self.back_indices);
+        self.back.fill([0, 0, 0, 255]);
+        self.back_indices.fill(0);
+        self.dirty = Some(screen_rect(WIDTH, HEIGHT));
+    }
+
+    // The shown screen as RGBA
+    pub fn frame(&self) -> &[u8] {
+        &self.front
+    }
+
+    // The palette index last drawn at each pixel, for frame logs
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = build_snapshot(WIDTH, HEIGHT);
+        snapshot.pixels.copy_from_slice(&self.front_indices);
+        snapshot
+    }
+
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BELOW: [u8; 4] = [100, 100, 100, 255];
+    const COLOR: [u8; 4] = [200, 50, 0, 255];
+
+    #[test]
+    fn palettes_load_argb_from_index_one() {
+        let mut screen = build_mega_screen();
+        let memory = [0xFF, 0x10, 0x20, 0x30, 0x80, 0x01, 0x02, 0x03];
+        screen.load_palette(&memory, 0, 2);
+        assert_eq!(screen.palette[0], [0, 0, 0, 255]);
+        assert_eq!(screen.palette[1], [0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(screen.palette[2], [0x01, 0x02, 0x03, 0x80]);
+        assert_eq!(screen.palette[3], [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn blend_modes() {
+        let mut screen = build_mega_screen();
+        let expected = [
+            (Blend::Normal, [200, 50, 0, 255]),
+            (Blend::Transparent25, [175, 63, 25, 255]),
+            (Blend::Transparent50, [150, 75, 50, 255]),
+            (Blend::Transparent75, [125, 88, 75, 255]),
+            (Blend::Add, [255, 150, 100, 255]),
+            (Blend::Multiply, [78, 19, 0, 255]),
+        ];
+        for (blend, color) in expected {
+            screen.set_blend(blend);
+            assert_eq!(screen.blend(BELOW, COLOR), color, "{:?}", blend);
+        }
+        // The color's own alpha scales the transparency
+        screen.set_blend(Blend::Normal);
+        assert_eq!(screen.blend(BELOW, [200, 50, 0, 128]), [150, 75, 50, 255]);
+        assert_eq!(blend_mode(6), None);
+    }
+
+    #[test]
+    fn present_shows_the_faded_frame_and_clears_the_next() {
+        let mut screen = build_mega_screen();
+        screen.load_palette(&[0xFF, 200, 100, 50], 0, 1);
+        screen.set_sprite_width(1);
+        screen.set_sprite_height(1);
+        screen.set_alpha(128);
+        screen.draw_sprite(&[1], 0, 2, 1);
+        screen.take_dirty();
+
+        screen.present();
+        let pixel = (WIDTH + 2) * 4;
+        assert_eq!(&screen.frame()[pixel..pixel + 4], &[100, 50, 25, 255]);
+        assert_eq!(screen.snapshot().pixel(2, 1), 1);
+        assert_eq!(screen.take_dirty(), Some(screen_rect(WIDTH, HEIGHT)));
+
+        screen.present();
+        assert_eq!(&screen.frame()[pixel..pixel + 4], &[0, 0, 0, 255]);
+        assert_eq!(screen.snapshot().pixel(2, 1), 0);
+    }
+}
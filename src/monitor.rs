@@ -0,0 +1,341 @@
+use std::io::BufRead;
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, TryRecvError, channel};
+
+use crate::cpu::Cpu;
+use crate::symbols::Symbols;
+
+const ROW_BYTES: usize = 16;
+const DEFAULT_ROWS: usize = 8;
+const DEFAULT_SPRITE_HEIGHT: usize = 8;
+const MAX_MATCHES: usize = 32;
+const FONT_END: usize = 80;
+
+const PC_STYLE: &str = "\x1b[7m";
+const I_STYLE: &str = "\x1b[1;33m";
+const FONT_STYLE: &str = "\x1b[2m";
+const RESET_STYLE: &str = "\x1b[0m";
+
+const HELP: &str = "Commands (numbers are hex; addresses may also be labels like draw+4):
+  d [ADDR] [ROWS]      Hex dump, continuing from the last dump by default
+  e ADDR BYTE...       Write bytes to memory (only while paused)
+  f BYTE...            Find a byte pattern; ?? matches any byte
+  s [HEIGHT]           Preview the bytes at I as an 8xHEIGHT sprite
+  r                    Show PC and I
+  k                    Show the call stack
+  o                    Step out: run until the current subroutine returns
+  p [BYTE]             Show the CHIP-8X output port, or send BYTE to FXFB
+  c                    Continue running
+  h                    Show this message";
+
+pub enum Request {
+    Continue,
+    StepOut,
+}
+
+// Commands are read from stdin on a background thread so the window keeps
+// redrawing while the emulator is paused.
+pub struct Monitor {
+    commands: Receiver<String>,
+    view: usize,
+    symbols: Option<Rc<Symbols>>,
+}
+
+pub fn start_monitor(symbols: Option<Rc<Symbols>>) -> Monitor {
+    let (sender, commands) = channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    Monitor {
+        commands,
+        view: 0,
+        symbols,
+    }
+}
+
+impl Monitor {
+    pub fn pause(&mut self, cpu: &Cpu) {
+        println!("Paused at {:03X}; type h for help, c to continue", cpu.pc());
+        self.view = (cpu.pc() as usize & !(ROW_BYTES - 1)).saturating_sub(ROW_BYTES * 2);
+        self.dump(cpu, self.view, DEFAULT_ROWS);
+    }
+
+    // Runs any commands typed since the last call and returns what the user
+    // asked the emulator to do next, if anything.
+    pub fn poll(&mut self, cpu: &mut Cpu, paused: bool) -> Option<Request> {
+        loop {
+            let line = match self.commands.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return None,
+            };
+            match self.execute(cpu, &line, paused) {
+                Ok(Some(request)) => return Some(request),
+                Ok(None) => {}
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    fn execute(
+        &mut self,
+        cpu: &mut Cpu,
+        line: &str,
+        paused: bool,
+    ) -> Result<Option<Request>, String> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(None);
+        };
+        let args: Vec<&str> = words.collect();
+
+        match command {
+            "d" => {
+                let start = match args.first() {
+                    Some(text) => self.parse_address(text)?,
+                    None => self.view,
+                };
+                let rows = match args.get(1) {
+                    Some(text) => parse_hex(text)?,
+                    None => DEFAULT_ROWS,
+                };
+                self.dump(cpu, start, rows);
+            }
+            "e" => {
+                if !paused {
+                    return Err("Memory can only be edited while paused".to_string());
+                }
+                let Some((address, bytes)) = args.split_first() else {
+                    return Err("Usage: e ADDR BYTE...".to_string());
+                };
+                let address = self.parse_address(address)?;
+                let bytes = bytes
+                    .iter()
+                    .map(|text| parse_byte(text))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                if bytes.is_empty() || address + bytes.len() > cpu.memory().len() {
+                    return Err("Usage: e ADDR BYTE... within 000-FFF".to_string());
+                }
+                for (offset, &byte) in bytes.iter().enumerate() {
+                    cpu.write_memory(address + offset, byte);
+                }
+                self.dump(cpu, address & !(ROW_BYTES - 1), 1);
+            }
+            "f" => {
+                let pattern = args
+                    .iter()
+                    .map(|&text| match text {
+                        "??" => Ok(None),
+                        _ => parse_byte(text).map(Some),
+                    })
+                    .collect::<Result<Vec<Option<u8>>, String>>()?;
+                if pattern.is_empty() {
+                    return Err("Usage: f BYTE...".to_string());
+                }
+                let matches: Vec<usize> = cpu
+                    .memory()
+                    .windows(pattern.len())
+                    .enumerate()
+                    .filter(|(_, window)| {
+                        window
+                            .iter()
+                            .zip(&pattern)
+                            .all(|(&byte, expected)| expected.is_none_or(|e| e == byte))
+                    })
+                    .map(|(address, _)| address)
+                    .collect();
+                let shown: Vec<String> = matches
+                    .iter()
+                    .take(MAX_MATCHES)
+                    .map(|address| format!("{:03X}", address))
+                    .collect();
+                println!("{} match(es) {}", matches.len(), shown.join(" "));
+                if let Some(&first) = matches.first() {
+                    self.view = first & !(ROW_BYTES - 1);
+                }
+            }
+            "s" => {
+                let height = match args.first() {
+                    Some(text) => parse_hex(text)?,
+                    None => DEFAULT_SPRITE_HEIGHT,
+                };
+                self.preview_sprite(cpu, height);
+            }
+            "r" => println!(
+                "PC={} I={}",
+                self.describe(cpu.pc().into()),
+                self.describe(cpu.i_register())
+            ),
+            "k" => {
+                println!("  #0  {}", self.describe(cpu.pc().into()));
+                for (depth, frame) in cpu.call_stack().iter().rev().enumerate() {
+                    let entry = frame
+                        .entry
+                        .map_or_else(|| "???".to_string(), |entry| self.describe(entry.into()));
+                    println!(
+                        "  #{}  {}  CALL {}",
+                        depth + 1,
+                        self.describe(frame.call_site.into()),
+                        entry
+                    );
+                }
+            }
+            "o" => {
+                if !paused {
+                    return Err("Step out is only available while paused".to_string());
+                }
+                let Some(frame) = cpu.call_stack().last() else {
+                    return Err("Not inside a subroutine".to_string());
+                };
+                println!(
+                    "Running until return to {}",
+                    self.describe(frame.return_address.into())
+                );
+                return Ok(Some(Request::StepOut));
+            }
+            "p" => match args.first() {
+                Some(text) => {
+                    let value = parse_byte(text)?;
+                    cpu.send_input(value);
+                    println!("Input port {:02X}", value);
+                }
+                None => println!("Output port {:02X}", cpu.output_port()),
+            },
+            "c" => return Ok(Some(Request::Continue)),
+            "h" | "help" => println!("{}", HELP),
+            _ => return Err(format!("Unknown command '{}'; type h for help", command)),
+        }
+        Ok(None)
+    }
+
+    // Labels win over hex, so a label such as "add" or "beef" names itself
+    fn parse_address(&self, text: &str) -> Result<usize, String> {
+        match self.symbols.as_ref().and_then(|s| s.resolve(text)) {
+            Some(address) => Ok(address as usize),
+            None => parse_hex(text),
+        }
+    }
+
+    fn describe(&self, address: u32) -> String {
+        let name = u16::try_from(address)
+            .ok()
+            .and_then(|address| self.symbols.as_ref()?.name(address));
+        match name {
+            Some(name) => format!("{:03X} ({})", address, name),
+            None => format!("{:03X}", address),
+        }
+    }
+
+    // PC is shown in reverse video, I in yellow and the font region dimmed
+    fn dump(&mut self, cpu: &Cpu, start: usize, rows: usize) {
+        let memory = cpu.memory();
+        let pc = cpu.pc() as usize;
+        let i = cpu.i_register() as usize;
+
+        let mut address = start.min(memory.len()) & !(ROW_BYTES - 1);
+        for _ in 0..rows {
+            if address >= memory.len() {
+                break;
+            }
+            let row = &memory[address..address + ROW_BYTES];
+            let mut hex = String::new();
+            let mut ascii = String::new();
+            for (offset, &byte) in row.iter().enumerate() {
+                let location = address + offset;
+                let style = if location == pc || location == pc + 1 {
+                    PC_STYLE
+                } else if location == i {
+                    I_STYLE
+                } else if location < FONT_END {
+                    FONT_STYLE
+                } else {
+                    ""
+                };
+                let character = if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                };
+                if style.is_empty() {
+                    hex.push_str(&format!(" {:02X}", byte));
+                    ascii.push(character);
+                } else {
+                    hex.push_str(&format!(" {}{:02X}{}", style, byte, RESET_STYLE));
+                    ascii.push_str(&format!("{}{}{}", style, character, RESET_STYLE));
+                }
+            }
+            println!("{:03X}:{}  |{}|", address, hex, ascii);
+            address += ROW_BYTES;
+        }
+        self.view = address % memory.len();
+    }
+
+    fn preview_sprite(&self, cpu: &Cpu, height: usize) {
+        let memory = cpu.memory();
+        let i = cpu.i_register() as usize;
+        println!("Sprite at I={:03X}:", i);
+        for row in 0..height {
+            let byte = memory[(i + row) % memory.len()];
+            let pixels: String = (0..8)
+                .map(|bit| {
+                    if byte & (0x80 >> bit) != 0 {
+                        "##"
+                    } else {
+                        ".."
+                    }
+                })
+                .collect();
+            println!("  {:02X}  {}", byte, pixels);
+        }
+    }
+}
+
+fn parse_hex(text: &str) -> Result<usize, String> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    usize::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex number '{}'", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    parse_hex(text)
+        .ok()
+        .and_then(|value| u8::try_from(value).ok())
+        .ok_or_else(|| format!("Invalid byte '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::symbols::build_symbols;
+
+    fn build_monitor(source: &str) -> Monitor {
+        let program = assemble(source).unwrap();
+        Monitor {
+            commands: channel().1,
+            view: 0,
+            symbols: Some(Rc::new(build_symbols(&program, Some(source)))),
+        }
+    }
+
+    #[test]
+    fn labels_are_tried_before_hex() {
+        let monitor = build_monitor(": main\n  jump main\n: beef\n  v0 := 1\n");
+        assert_eq!(monitor.parse_address("beef"), Ok(0x202));
+        assert_eq!(monitor.parse_address("beef+1"), Ok(0x203));
+        assert_eq!(monitor.parse_address("bee"), Ok(0xBEE));
+        assert!(monitor.parse_address("main2").is_err());
+    }
+
+    #[test]
+    fn wide_addresses_are_shown_in_full() {
+        let monitor = build_monitor(": main\n  jump main\n");
+        assert_eq!(monitor.describe(0x200), "200 (main)");
+        assert_eq!(monitor.describe(0x123456), "123456");
+    }
+}
@@ -0,0 +1,110 @@
+#[derive(Clone)]
+pub struct Palette {
+    pub name: String,
+    colors: Vec<[u8; 4]>,
+}
+
+// Colors are ordered by pixel value: background, first plane, second plane,
+// both planes, and so on for 16-color palettes.
+const THEMES: [(&str, [u32; 4]); 5] = [
+    ("classic", [0x0A0E27, 0x00FF9F, 0x009FFF, 0xFFFFFF]),
+    ("amber", [0x1A0F00, 0xFFB000, 0xB36B00, 0xFFE0A0]),
+    ("lcd", [0x9BBC0F, 0x0F380F, 0x8BAC0F, 0x306230]),
+    ("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+    ("high-contrast", [0x000000, 0xFFFFFF, 0xFF0000, 0xFFFF00]),
+];
+
+// The VP-590 color board used by CHIP-8X: eight foreground colors, and the
+// four backgrounds that 02A0 cycles through
+const CHIP8X_FOREGROUNDS: [u32; 8] = [
+    0x000000, 0xFF0000, 0x0000FF, 0xFF00FF, 0x00FF00, 0xFFFF00, 0x00FFFF, 0xFFFFFF,
+];
+const CHIP8X_BACKGROUNDS: [u32; 4] = [0x000080, 0x000000, 0x008000, 0x800000];
+
+pub fn build_palette(name: &str, colors: Vec<[u8; 4]>) -> Result<Palette, String> {
+    if !matches!(colors.len(), 2 | 4 | 16) {
+        return Err(format!(
+            "Palette '{}' has {} colors; expected 2, 4 or 16",
+            name,
+            colors.len()
+        ));
+    }
+    Ok(Palette {
+        name: name.to_string(),
+        colors,
+    })
+}
+
+pub fn builtin_palettes() -> Vec<Palette> {
+    THEMES
+        .iter()
+        .map(|(name, colors)| Palette {
+            name: name.to_string(),
+            colors: colors.iter().map(|&c| rgb(c)).collect(),
+        })
+        .collect()
+}
+
+pub fn parse_palette(name: &str, text: &str) -> Result<Palette, String> {
+    let colors = text
+        .split(',')
+        .map(|hex| parse_color(hex.trim()).ok_or_else(|| format!("Invalid color '{}'", hex)))
+        .collect::<Result<Vec<_>, _>>()?;
+    build_palette(name, colors)
+}
+
+pub fn parse_color(text: &str) -> Option<[u8; 4]> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok().map(rgb)
+}
+
+// Replaces the palette on CHIP-8X, where the program picks the colors
+pub fn chip8x_shade(background: u8, foreground: u8, intensity: f32) -> [u8; 4] {
+    blend(
+        rgb(CHIP8X_BACKGROUNDS[background as usize % 4]),
+        rgb(CHIP8X_FOREGROUNDS[foreground as usize % 8]),
+        intensity,
+    )
+}
+
+fn blend(background: [u8; 4], foreground: [u8; 4], intensity: f32) -> [u8; 4] {
+    let mut color = background;
+    for channel in 0..3 {
+        let from = background[channel] as f32;
+        let to = foreground[channel] as f32;
+        color[channel] = (from + (to - from) * intensity).round() as u8;
+    }
+    color
+}
+
+fn rgb(value: u32) -> [u8; 4] {
+    [(value >> 16) as u8, (value >> 8) as u8, value as u8, 255]
+}
+
+impl Palette {
+    pub fn color(&self, value: u8) -> [u8; 4] {
+        let index = (value as usize).min(self.colors.len() - 1);
+        self.colors[index]
+    }
+
+    pub fn shade(&self, value: u8, intensity: f32) -> [u8; 4] {
+        blend(self.colors[0], self.color(value), intensity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_are_six_hex_digits() {
+        assert_eq!(parse_color("#FF6600"), Some([0xFF, 0x66, 0x00, 0xFF]));
+        assert_eq!(parse_color("996600"), Some([0x99, 0x66, 0x00, 0xFF]));
+        assert_eq!(parse_color("+12345"), None);
+        assert_eq!(parse_color("#+12345"), None);
+        assert_eq!(parse_color("#FF660"), None);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::display::{ColorMap, Rect, Snapshot, merge, screen_rect};
+use crate::palette::{Palette, chip8x_shade};
+
+pub const DEFAULT_DECAY: f32 = 0.6;
+// Below this a fading pixel no longer changes its 8-bit color
+const MIN_INTENSITY: f32 = 1.0 / 255.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Persistence {
+    Off,
+    Decay(f32),
+    MaxOfTwo,
+}
+
+// Smooths out the flicker caused by sprites being erased and redrawn on
+// alternate frames. Runs once per emulated frame on the display buffer so
+// it behaves identically with or without a window.
+pub struct Phosphor {
+    mode: Persistence,
+    width: usize,
+    height: usize,
+    values: Vec<u8>,
+    intensity: Vec<f32>,
+    previous: Vec<u8>,
+    // Cleared when the mode changes so the next update covers the screen
+    settled: bool,
+}
+
+pub fn build_phosphor(mode: Persistence) -> Phosphor {
+    Phosphor {
+        mode,
+        width: 0,
+        height: 0,
+        values: Vec::new(),
+        intensity: Vec::new(),
+        previous: Vec::new(),
+        settled: false,
+    }
+}
+
+pub fn parse_persistence(text: &str) -> Result<Persistence, String> {
+    match text.split_once(':') {
+        None if text == "off" => Ok(Persistence::Off),
+        None if text == "max2" => Ok(Persistence::MaxOfTwo),
+        None if text == "decay" => Ok(Persistence::Decay(DEFAULT_DECAY)),
+        Some(("decay", amount)) => match amount.parse::<f32>() {
+            Ok(decay) if (0.0..1.0).contains(&decay) => Ok(Persistence::Decay(decay)),
+            _ => Err(format!("Decay must be between 0 and 1, got '{}'", amount)),
+        },
+        _ => Err(format!(
+            "Unknown ghosting mode '{}'; expected off, max2, decay or decay:<amount>",
+            text
+        )),
+    }
+}
+
+impl Phosphor {
+    pub fn mode(&self) -> Persistence {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Persistence) {
+        self.mode = mode;
+        self.settled = false;
+    }
+
+    // Takes the region of the display that changed, and returns the region
+    // whose shade changed. Without ghosting only the changed region can
+    // differ; the other modes also fade pixels that were not touched.
+    pub fn update(&mut self, display: &Snapshot, dirty: Option<Rect>) -> Option<Rect> {
+        // A new screen size has nothing drawn for it yet
+        let mut changed = None;
+        if (self.width, self.height) != (display.width, display.height) {
+            self.resize(display.width, display.height);
+            changed = Some(self.bounds());
+        }
+        let region = match (self.mode, dirty) {
+            (Persistence::Off, None) if self.settled => return None,
+            (Persistence::Off, Some(dirty)) if self.settled => dirty,
+            _ => self.bounds(),
+        };
+        self.settled = true;
+
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                let index = y * self.width + x;
+                let current = display.pixels[index];
+                let previous = self.previous[index];
+
+                let (value, intensity) = match self.mode {
+                    Persistence::Off => (current, if current != 0 { 1.0 } else { 0.0 }),
+                    Persistence::MaxOfTwo => {
+                        let value = if current != 0 { current } else { previous };
+                        (value, if value != 0 { 1.0 } else { 0.0 })
+                    }
+                    Persistence::Decay(decay) if current == 0 => {
+                        let intensity = self.intensity[index] * decay;
+                        if intensity < MIN_INTENSITY {
+                            (0, 0.0)
+                        } else {
+                            (self.values[index], intensity)
+                        }
+                    }
+                    Persistence::Decay(_) => (current, 1.0),
+                };
+
+                if self.values[index] != value || self.intensity[index] != intensity {
+                    let pixel = Rect {
+                        x,
+                        y,
+                        width: 1,
+                        height: 1,
+                    };
+                    changed = merge(changed, Some(pixel));
+                }
+                self.values[index] = value;
+                self.intensity[index] = intensity;
+            }
+        }
+        self.previous.copy_from_slice(&display.pixels);
+        changed
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.values = vec![0; width * height];
+        self.intensity = vec![0.0; width * height];
+        self.previous = vec![0; width * height];
+        self.settled = false;
+    }
+
+    pub fn bounds(&self) -> Rect {
+        screen_rect(self.width, self.height)
+    }
+
+    pub fn value(&self, x: usize, y: usize) -> u8 {
+        self.values[y * self.width + x]
+    }
+
+    pub fn intensity(&self, x: usize, y: usize) -> f32 {
+        self.intensity[y * self.width + x]
+    }
+}
+
+// Converts the phosphor state to RGBA pixels in the given palette, or in the
+// program's own colors when it has a color map
+pub fn render_display(
+    phosphor: &Phosphor,
+    frame: &mut [u8],
+    palette: &Palette,
+    colors: Option<&ColorMap>,
+) {
+    render_region(phosphor, frame, palette, colors, phosphor.bounds());
+}
+
+// Like render_display, but only rewrites the pixels inside the region
+pub fn render_region(
+    phosphor: &Phosphor,
+    frame: &mut [u8],
+    palette: &Palette,
+    colors: Option<&ColorMap>,
+    region: Rect,
+) {
+    let bottom = (region.y + region.height).min(phosphor.height);
+    let right = (region.x + region.width).min(phosphor.width);
+    for y in region.y..bottom {
+        for x in region.x..right {
+            let intensity = phosphor.intensity(x, y);
+            let color = match colors {
+                Some(colors) => {
+                    chip8x_shade(colors.background(), colors.foreground(x, y), intensity)
+                }
+                None => palette.shade(phosphor.value(x, y), intensity),
+            };
+            let offset = (y * phosphor.width + x) * 4;
+            frame[offset..offset + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::build_snapshot;
+
+    fn frame(lit: &[(usize, usize)]) -> Snapshot {
+        let mut snapshot = build_snapshot(4, 2);
+        for &(x, y) in lit {
+            snapshot.pixels[y * 4 + x] = 1;
+        }
+        snapshot
+    }
+
+    fn pixel(x: usize, y: usize) -> Option<Rect> {
+        Some(Rect {
+            x,
+            y,
+            width: 1,
+            height: 1,
+        })
+    }
+
+    #[test]
+    fn off_follows_the_display_and_only_reports_dirty_changes() {
+        let mut phosphor = build_phosphor(Persistence::Off);
+        assert_eq!(phosphor.update(&frame(&[]), None), Some(screen_rect(4, 2)));
+
+        assert_eq!(phosphor.update(&frame(&[(1, 0)]), pixel(1, 0)), pixel(1, 0));
+        assert_eq!(phosphor.intensity(1, 0), 1.0);
+        assert_eq!(phosphor.value(1, 0), 1);
+
+        assert_eq!(phosphor.update(&frame(&[(1, 0)]), None), None);
+
+        assert_eq!(phosphor.update(&frame(&[]), pixel(1, 0)), pixel(1, 0));
+        assert_eq!(phosphor.intensity(1, 0), 0.0);
+    }
+
+    #[test]
+    fn decay_fades_pixels_that_go_dark() {
+        let mut phosphor = build_phosphor(Persistence::Decay(0.5));
+        phosphor.update(&frame(&[]), None);
+
+        assert_eq!(phosphor.update(&frame(&[(2, 1)]), pixel(2, 1)), pixel(2, 1));
+        assert_eq!(phosphor.intensity(2, 1), 1.0);
+
+        assert_eq!(phosphor.update(&frame(&[]), pixel(2, 1)), pixel(2, 1));
+        assert_eq!(phosphor.intensity(2, 1), 0.5);
+        assert_eq!(phosphor.value(2, 1), 1);
+
+        // Fading carries on without any display changes
+        assert_eq!(phosphor.update(&frame(&[]), None), pixel(2, 1));
+        assert_eq!(phosphor.intensity(2, 1), 0.25);
+
+        assert_eq!(phosphor.update(&frame(&[(2, 1)]), pixel(2, 1)), pixel(2, 1));
+        assert_eq!(phosphor.intensity(2, 1), 1.0);
+    }
+
+    #[test]
+    fn decay_goes_dark_once_too_faint_to_see() {
+        let mut phosphor = build_phosphor(Persistence::Decay(0.5));
+        phosphor.update(&frame(&[(2, 1)]), None);
+        phosphor.update(&frame(&[]), pixel(2, 1));
+
+        // 1/256 would round to the background color, so it snaps to dark
+        let mut frames = 1;
+        while phosphor.update(&frame(&[]), None).is_some() {
+            frames += 1;
+            assert!(frames < 16, "pixel never settled");
+        }
+        assert_eq!(frames, 8);
+        assert_eq!(phosphor.intensity(2, 1), 0.0);
+        assert_eq!(phosphor.value(2, 1), 0);
+    }
+
+    #[test]
+    fn max_of_two_keeps_pixels_lit_for_one_extra_frame() {
+        let mut phosphor = build_phosphor(Persistence::MaxOfTwo);
+        phosphor.update(&frame(&[]), None);
+
+        assert_eq!(phosphor.update(&frame(&[(0, 0)]), pixel(0, 0)), pixel(0, 0));
+        assert_eq!(phosphor.intensity(0, 0), 1.0);
+
+        assert_eq!(phosphor.update(&frame(&[]), pixel(0, 0)), None);
+        assert_eq!(phosphor.intensity(0, 0), 1.0);
+
+        assert_eq!(phosphor.update(&frame(&[]), None), pixel(0, 0));
+        assert_eq!(phosphor.intensity(0, 0), 0.0);
+    }
+
+    #[test]
+    fn resizing_reports_the_whole_screen() {
+        let mut phosphor = build_phosphor(Persistence::Off);
+        phosphor.update(&frame(&[]), None);
+        let larger = build_snapshot(8, 4);
+        assert_eq!(phosphor.update(&larger, None), Some(screen_rect(8, 4)));
+        assert_eq!(phosphor.bounds(), screen_rect(8, 4));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::quirks::{Quirks, build_quirks};
+
+// The interpreter a ROM was written for. They share the CHIP-8 instruction
+// set but differ in where programs live and in a few extra opcodes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Platform {
+    Chip8,
+    // COSMAC VIP with the VP-590 color board and a second keypad
+    Chip8X,
+    // The VIP two-page interpreter, with a 64x64 screen
+    Chip8HiRes,
+    // Starts out as SCHIP 1.1, and 0011 switches to a 256x192 screen in 256
+    // colors. Memory is 16MB either way.
+    MegaChip,
+    // SUPER-CHIP on the HP-48, with a 128x64 screen. 1.0 is the version
+    // CHIP-48 programs grew up on; 1.1 added scrolling.
+    Schip10,
+    Schip11,
+}
+
+pub fn parse_platform(text: &str) -> Result<Platform, String> {
+    match text {
+        "chip8" => Ok(Platform::Chip8),
+        "chip8x" => Ok(Platform::Chip8X),
+        "chip8-hires" => Ok(Platform::Chip8HiRes),
+        "megachip" => Ok(Platform::MegaChip),
+        "schip1.0" => Ok(Platform::Schip10),
+        "schip1.1" => Ok(Platform::Schip11),
+        _ => Err(format!(
+            "Unknown platform '{}'; expected chip8, chip8x, chip8-hires, megachip, schip1.0 or schip1.1",
+            text
+        )),
+    }
+}
+
+impl Platform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::Chip8X => "chip8x",
+            Platform::Chip8HiRes => "chip8-hires",
+            Platform::MegaChip => "megachip",
+            Platform::Schip10 => "schip1.0",
+            Platform::Schip11 => "schip1.1",
+        }
+    }
+
+    // The CHIP-8X interpreter is larger and takes up 0x200-0x2FF as well
+    pub fn load_address(&self) -> u16 {
+        match self {
+            Platform::Chip8X => 0x300,
+            _ => 0x200,
+        }
+    }
+
+    // Hi-res programs start with a patch to the interpreter, and their own
+    // code follows it at 0x2C0
+    pub fn entry_point(&self) -> u16 {
+        match self {
+            Platform::Chip8HiRes => 0x2C0,
+            _ => self.load_address(),
+        }
+    }
+
+    // MegaChip addresses memory through a 24-bit I
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::MegaChip => 0x1000000,
+            _ => 0x1000,
+        }
+    }
+
+    // SCHIP always keeps a 128x64 screen, and draws lores pixels as 2x2
+    // blocks on it. MegaChip uses it too outside MegaChip mode.
+    pub fn display_size(&self) -> (usize, usize) {
+        match self {
+            Platform::Chip8HiRes => (64, 64),
+            Platform::Schip10 | Platform::Schip11 | Platform::MegaChip => (128, 64),
+            _ => (64, 32),
+        }
+    }
+
+    // Whether the SCHIP screen and opcodes are there
+    pub fn is_schip(&self) -> bool {
+        matches!(
+            self,
+            Platform::Schip10 | Platform::Schip11 | Platform::MegaChip
+        )
+    }
+
+    // The behavior programs for the platform expect, before any cartridge
+    // settings are applied
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            // How FX55 and FX65 move I is fixed by the SCHIP version rather
+            // than the load/store quirk. MegaChip builds on SCHIP 1.1.
+            Platform::Schip10 | Platform::Schip11 | Platform::MegaChip => Quirks {
+                shift: true,
+                jump: true,
+                logic: false,
+                clip: true,
+                display_wait: false,
+                ..build_quirks()
+            },
+            _ => build_quirks(),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::disassembler::disassemble;
+use crate::platform::Platform;
+use crate::symbols::Symbols;
+
+const HOT_ADDRESSES: usize = 20;
+const ROOT_NAME: &str = "main";
+
+#[derive(Default)]
+struct Subroutine {
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+// Counts are in emulated cycles. The call stack is rebuilt from 2NNN and
+// 00EE so samples can be attributed to subroutines.
+pub struct Profiler {
+    counts: Vec<u64>,
+    opcodes: Vec<u16>,
+    stack: Vec<u16>,
+    folded: HashMap<Vec<u16>, u64>,
+    subroutines: HashMap<u16, Subroutine>,
+    executed: u64,
+    key_wait_cycles: u64,
+    display_wait_cycles: u64,
+}
+
+pub fn build_profiler() -> Profiler {
+    Profiler {
+        counts: vec![0; 4096],
+        opcodes: vec![0; 4096],
+        stack: Vec::new(),
+        folded: HashMap::new(),
+        subroutines: HashMap::new(),
+        executed: 0,
+        key_wait_cycles: 0,
+        display_wait_cycles: 0,
+    }
+}
+
+impl Profiler {
+    pub fn record_display_wait(&mut self) {
+        self.display_wait_cycles += 1;
+    }
+
+    pub fn record(&mut self, pc: u16, opcode: u16, next_pc: u16) {
+        let address = pc as usize % self.counts.len();
+        self.counts[address] += 1;
+        self.opcodes[address] = opcode;
+        self.executed += 1;
+
+        if opcode & 0xF0FF == 0xF00A && next_pc == pc {
+            self.key_wait_cycles += 1;
+        }
+
+        *self.folded.entry(self.stack.clone()).or_insert(0) += 1;
+        if let Some(&entry) = self.stack.last() {
+            self.subroutines.entry(entry).or_default().exclusive += 1;
+        }
+        let mut seen = Vec::with_capacity(self.stack.len());
+        for &entry in &self.stack {
+            if !seen.contains(&entry) {
+                seen.push(entry);
+                self.subroutines.entry(entry).or_default().inclusive += 1;
+            }
+        }
+
+        match opcode {
+            0x00EE => {
+                self.stack.pop();
+            }
+            _ if opcode & 0xF000 == 0x2000 => {
+                let entry = opcode & 0x0FFF;
+                self.subroutines.entry(entry).or_default().calls += 1;
+                self.stack.push(entry);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn write_report(
+        &self,
+        writer: &mut impl Write,
+        platform: Platform,
+        symbols: Option<&Symbols>,
+    ) -> std::io::Result<()> {
+        let name = |address: u16| {
+            symbols
+                .and_then(|symbols| symbols.name(address))
+                .unwrap_or_default()
+        };
+        let total = self.executed + self.display_wait_cycles;
+        let percent = |count: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                count as f64 * 100.0 / total as f64
+            }
+        };
+
+        writeln!(writer, "Cycles: {}", total)?;
+        writeln!(
+            writer,
+            "  executing:           {:10} ({:5.1}%)",
+            self.executed - self.key_wait_cycles,
+            percent(self.executed - self.key_wait_cycles)
+        )?;
+        writeln!(
+            writer,
+            "  waiting for key:     {:10} ({:5.1}%)",
+            self.key_wait_cycles,
+            percent(self.key_wait_cycles)
+        )?;
+        writeln!(
+            writer,
+            "  waiting for display: {:10} ({:5.1}%)",
+            self.display_wait_cycles,
+            percent(self.display_wait_cycles)
+        )?;
+
+        let mut addresses: Vec<usize> = (0..self.counts.len())
+            .filter(|&a| self.counts[a] > 0)
+            .collect();
+        addresses.sort_by_key(|&a| std::cmp::Reverse(self.counts[a]));
+
+        writeln!(writer)?;
+        writeln!(writer, "Hottest addresses:")?;
+        writeln!(writer, "  addr {:<20}      count       %  instruction", "")?;
+        for &address in addresses.iter().take(HOT_ADDRESSES) {
+            writeln!(
+                writer,
+                "  {:03X}  {:<20}{:10} {:6.1}%  {}",
+                address,
+                name(address as u16),
+                self.counts[address],
+                percent(self.counts[address]),
+                disassemble(self.opcodes[address], platform, symbols)
+            )?;
+        }
+
+        let mut subroutines: Vec<(&u16, &Subroutine)> = self.subroutines.iter().collect();
+        subroutines.sort_by_key(|(_, s)| std::cmp::Reverse(s.inclusive));
+
+        writeln!(writer)?;
+        writeln!(writer, "Subroutines:")?;
+        writeln!(
+            writer,
+            "  entry {:<20}     calls   inclusive   exclusive",
+            ""
+        )?;
+        for (&entry, subroutine) in subroutines {
+            writeln!(
+                writer,
+                "  {:03X}   {:<20}{:10}  {:10}  {:10}",
+                entry,
+                name(entry),
+                subroutine.calls,
+                subroutine.inclusive,
+                subroutine.exclusive
+            )?;
+        }
+        Ok(())
+    }
+
+    // One line per unique call stack in the format consumed by
+    // flamegraph.pl and inferno: "main;0x2A4;0x31C 1234"
+    pub fn write_folded(
+        &self,
+        writer: &mut impl Write,
+        symbols: Option<&Symbols>,
+    ) -> std::io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self
+            .folded
+            .iter()
+            .map(|(stack, &count)| {
+                let mut names = vec![ROOT_NAME.to_string()];
+                names.extend(stack.iter().map(|&entry| {
+                    symbols
+                        .and_then(|symbols| symbols.name(entry))
+                        .unwrap_or_else(|| format!("0x{:03X}", entry))
+                }));
+                (names.join(";"), count)
+            })
+            .collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn save_reports(
+    profiler: &Profiler,
+    platform: Platform,
+    symbols: Option<&Symbols>,
+    report: Option<&str>,
+    folded: Option<&str>,
+) {
+    if let Some(location) = report {
+        let result = if location == "-" {
+            profiler.write_report(&mut std::io::stdout(), platform, symbols)
+        } else {
+            File::create(location).and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                profiler.write_report(&mut writer, platform, symbols)?;
+                writer.flush()
+            })
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to write profile '{}': {}", location, e);
+        }
+    }
+
+    if let Some(location) = folded {
+        let result = File::create(location).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            profiler.write_folded(&mut writer, symbols)?;
+            writer.flush()
+        });
+        if let Err(e) = result {
+            eprintln!("Failed to write folded stacks '{}': {}", location, e);
+        }
+    }
+}
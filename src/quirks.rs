@@ -0,0 +1,21 @@
+#[derive(Clone, Copy)]
+pub struct Quirks {
+    pub shift: bool,
+    pub load_store: bool,
+    pub jump: bool,
+    pub logic: bool,
+    pub clip: bool,
+    pub display_wait: bool,
+}
+
+pub fn build_quirks() -> Quirks {
+    // Defaults match the original COSMAC VIP interpreter
+    Quirks {
+        shift: false,
+        load_store: false,
+        jump: false,
+        logic: true,
+        clip: true,
+        display_wait: true,
+    }
+}
@@ -0,0 +1,91 @@
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScaleMode {
+    Integer,
+    Fit,
+    Stretch,
+}
+
+pub fn parse_scale_mode(text: &str) -> Result<ScaleMode, String> {
+    match text {
+        "integer" => Ok(ScaleMode::Integer),
+        "fit" => Ok(ScaleMode::Fit),
+        "stretch" => Ok(ScaleMode::Stretch),
+        _ => Err(format!(
+            "Unknown scale mode '{}'; expected integer, fit or stretch",
+            text
+        )),
+    }
+}
+
+impl ScaleMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleMode::Integer => "integer",
+            ScaleMode::Fit => "fit",
+            ScaleMode::Stretch => "stretch",
+        }
+    }
+
+    pub fn next(&self) -> ScaleMode {
+        match self {
+            ScaleMode::Integer => ScaleMode::Fit,
+            ScaleMode::Fit => ScaleMode::Stretch,
+            ScaleMode::Stretch => ScaleMode::Integer,
+        }
+    }
+}
+
+// Area of the surface covered by the image as (x, y, width, height)
+pub fn target_rect(
+    mode: ScaleMode,
+    source: (usize, usize),
+    surface: (usize, usize),
+) -> (usize, usize, usize, usize) {
+    let (width, height) = match mode {
+        ScaleMode::Stretch => surface,
+        ScaleMode::Fit => {
+            let scale =
+                (surface.0 as f64 / source.0 as f64).min(surface.1 as f64 / source.1 as f64);
+            (
+                ((source.0 as f64 * scale) as usize).max(1),
+                ((source.1 as f64 * scale) as usize).max(1),
+            )
+        }
+        ScaleMode::Integer => {
+            let scale = (surface.0 / source.0).min(surface.1 / source.1).max(1);
+            (source.0 * scale, source.1 * scale)
+        }
+    };
+    let (width, height) = (width.min(surface.0), height.min(surface.1));
+    (
+        (surface.0 - width) / 2,
+        (surface.1 - height) / 2,
+        width,
+        height,
+    )
+}
+
+// Nearest-neighbour scale of an RGBA image onto a larger RGBA surface,
+// filling the letterbox bars with the border color
+pub fn blit(
+    mode: ScaleMode,
+    source: &[u8],
+    source_size: (usize, usize),
+    dest: &mut [u8],
+    dest_size: (usize, usize),
+    border: [u8; 4],
+) {
+    let (left, top, width, height) = target_rect(mode, source_size, dest_size);
+
+    for (i, pixel) in dest.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % dest_size.0, i / dest_size.0);
+        if x < left || y < top || x >= left + width || y >= top + height {
+            pixel.copy_from_slice(&border);
+            continue;
+        }
+        let sx = (x - left) * source_size.0 / width;
+        let sy = (y - top) * source_size.1 / height;
+        let s = (sy * source_size.0 + sx) * 4;
+        pixel.copy_from_slice(&source[s..s + 4]);
+    }
+}
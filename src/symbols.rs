@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::assembler::{Program, parse_number};
+
+// Labels and the source line each byte of the program was assembled from
+pub struct Symbols {
+    labels: Vec<(u16, String)>,
+    lines: BTreeMap<u16, usize>,
+    source: Option<String>,
+}
+
+pub fn build_symbols(program: &Program, source: Option<&str>) -> Symbols {
+    let mut labels: Vec<(u16, String)> = program
+        .labels
+        .iter()
+        .map(|(name, &address)| (address, name.clone()))
+        .collect();
+    labels.sort();
+    Symbols {
+        labels,
+        lines: program.lines.clone(),
+        source: source.map(str::to_string),
+    }
+}
+
+// Symbol files are plain text, one entry per line:
+//
+//   source game.8o
+//   label draw_piece 0x2A4
+//   line 0x2A4 12
+//
+// A relative source path is resolved against the symbol file's directory.
+pub fn load_symbols(location: &str) -> Result<Symbols, String> {
+    let text = std::fs::read_to_string(location)
+        .map_err(|e| format!("Failed to read symbol file '{}': {}", location, e))?;
+
+    let mut labels = Vec::new();
+    let mut lines = BTreeMap::new();
+    let mut source = None;
+    for (index, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line
+            .split('#')
+            .next()
+            .unwrap_or("")
+            .split_whitespace()
+            .collect();
+        let error = || {
+            format!(
+                "{}:{}: invalid entry '{}'",
+                location,
+                index + 1,
+                line.trim()
+            )
+        };
+        let address = |text: &str| {
+            parse_number(text)
+                .filter(|address| (0..0x1000).contains(address))
+                .map(|address| address as u16)
+        };
+        match words.as_slice() {
+            [] => {}
+            ["source", path] => {
+                let path = Path::new(location)
+                    .parent()
+                    .map_or_else(|| Path::new(path).to_path_buf(), |dir| dir.join(path));
+                source = Some(path.to_string_lossy().into_owned());
+            }
+            ["label", name, value] => {
+                labels.push((address(value).ok_or_else(error)?, name.to_string()))
+            }
+            ["line", value, number] => {
+                let number = number.parse().map_err(|_| error())?;
+                lines.insert(address(value).ok_or_else(error)?, number);
+            }
+            _ => return Err(error()),
+        }
+    }
+    labels.sort();
+    Ok(Symbols {
+        labels,
+        lines,
+        source,
+    })
+}
+
+pub fn save_symbols(symbols: &Symbols, location: &str) -> Result<(), String> {
+    let mut text = String::new();
+    if let Some(source) = &symbols.source {
+        let _ = writeln!(text, "source {}", source);
+    }
+    for (address, name) in &symbols.labels {
+        let _ = writeln!(text, "label {} 0x{:03X}", name, address);
+    }
+    for (address, line) in &symbols.lines {
+        let _ = writeln!(text, "line 0x{:03X} {}", address, line);
+    }
+    std::fs::write(location, text)
+        .map_err(|e| format!("Failed to write symbol file '{}': {}", location, e))
+}
+
+// Symbols stored next to a ROM, e.g. tetris.ch8 -> tetris.sym
+pub fn symbols_location(rom_path: &str) -> String {
+    Path::new(rom_path)
+        .with_extension("sym")
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl Symbols {
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, name)| name == label)
+            .map(|&(address, _)| address)
+    }
+
+    // Accepts a label with an optional offset, e.g. "draw_piece+4"
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        let (label, offset) = match text.split_once('+') {
+            Some((label, offset)) => (label, parse_number(offset.trim())?),
+            None => (text, 0),
+        };
+        let address = self.address_of(label.trim())? as i32 + offset;
+        (0..0x1000).contains(&address).then_some(address as u16)
+    }
+
+    // The closest label at or before an address, e.g. "draw_piece+4"
+    pub fn name(&self, address: u16) -> Option<String> {
+        let index = self.labels.partition_point(|&(start, _)| start <= address);
+        let (start, name) = self.labels.get(index.checked_sub(1)?)?;
+        Some(if *start == address {
+            name.clone()
+        } else {
+            format!("{}+{}", name, address - start)
+        })
+    }
+
+    pub fn line(&self, address: u16) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    // First address assembled from the given line, or from the next line
+    // that produced any code. Returns the line actually used as well.
+    pub fn address_of_line(&self, line: usize) -> Option<(u16, usize)> {
+        let actual = self.lines.values().copied().filter(|&l| l >= line).min()?;
+        self.lines
+            .iter()
+            .find(|&(_, &l)| l == actual)
+            .map(|(&address, _)| (address, actual))
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::rc::Rc;
+
+use crate::disassembler::disassemble;
+use crate::platform::Platform;
+use crate::symbols::Symbols;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+pub struct TraceOptions {
+    pub output: Option<String>,
+    pub format: TraceFormat,
+    pub pc_range: Option<(u32, u32)>,
+    pub opcode_classes: Option<Vec<u8>>,
+    pub frame_range: Option<(u32, u32)>,
+    pub ring_size: Option<usize>,
+}
+
+pub fn build_trace_options() -> TraceOptions {
+    TraceOptions {
+        output: None,
+        format: TraceFormat::Text,
+        pc_range: None,
+        opcode_classes: None,
+        frame_range: None,
+        ring_size: None,
+    }
+}
+
+pub struct TraceEntry {
+    pub frame: u32,
+    pub pc: u16,
+    pub opcode: u16,
+    pub changed: Vec<(usize, u8)>,
+    pub i_register: u32,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+// In ring mode nothing is written while running; the last N entries are
+// kept in memory and dumped when the CPU hits a fault.
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    options: TraceOptions,
+    frame: u32,
+    ring: Option<VecDeque<TraceEntry>>,
+    fault_reported: bool,
+    symbols: Option<Rc<Symbols>>,
+    platform: Platform,
+}
+
+pub fn create_tracer(options: TraceOptions) -> Result<Tracer, String> {
+    let writer: Box<dyn Write> = match options.output.as_deref() {
+        None | Some("-") => Box::new(std::io::stderr()),
+        Some(location) => {
+            let file = File::create(location)
+                .map_err(|e| format!("Failed to create trace '{}': {}", location, e))?;
+            Box::new(BufWriter::new(file))
+        }
+    };
+    let ring = options.ring_size.map(VecDeque::with_capacity);
+
+    Ok(Tracer {
+        writer,
+        options,
+        frame: 0,
+        ring,
+        fault_reported: false,
+        symbols: None,
+        platform: Platform::Chip8,
+    })
+}
+
+pub fn parse_range(text: &str) -> Result<(u32, u32), String> {
+    let parse = |part: &str| {
+        let part = part.trim();
+        match part.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => part.parse(),
+        }
+        .map_err(|_| format!("Invalid number '{}' in range '{}'", part, text))
+    };
+    match text.split_once('-') {
+        Some((start, end)) => Ok((parse(start)?, parse(end)?)),
+        None => parse(text).map(|value| (value, value)),
+    }
+}
+
+pub fn parse_opcode_classes(text: &str) -> Result<Vec<u8>, String> {
+    text.split(',')
+        .map(|class| {
+            u8::from_str_radix(class.trim(), 16)
+                .ok()
+                .filter(|&c| c < 16)
+                .ok_or_else(|| format!("Invalid opcode class '{}'; expected 0-F", class))
+        })
+        .collect()
+}
+
+pub fn parse_trace_format(text: &str) -> Result<TraceFormat, String> {
+    match text {
+        "text" => Ok(TraceFormat::Text),
+        "binary" => Ok(TraceFormat::Binary),
+        _ => Err(format!(
+            "Unknown trace format '{}'; expected text or binary",
+            text
+        )),
+    }
+}
+
+impl Tracer {
+    pub fn set_symbols(&mut self, symbols: Rc<Symbols>) {
+        self.symbols = Some(symbols);
+    }
+
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn wants(&self, pc: u16, opcode: u16) -> bool {
+        let in_range = |range: Option<(u32, u32)>, value: u32| {
+            range.is_none_or(|(start, end)| (start..=end).contains(&value))
+        };
+        let class = (opcode >> 12) as u8;
+        in_range(self.options.pc_range, pc as u32)
+            && in_range(self.options.frame_range, self.frame)
+            && self
+                .options
+                .opcode_classes
+                .as_ref()
+                .is_none_or(|classes| classes.contains(&class))
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) {
+        match &mut self.ring {
+            Some(ring) => {
+                if ring.len() == self.options.ring_size.unwrap_or(0) {
+                    ring.pop_front();
+                }
+                ring.push_back(entry);
+            }
+            None => self.write_entry(&entry),
+        }
+    }
+
+    pub fn report_fault(&mut self, message: &str) {
+        if self.fault_reported {
+            return;
+        }
+        self.fault_reported = true;
+
+        if let Some(ring) = self.ring.take() {
+            eprintln!("{}; dumping the last {} instructions", message, ring.len());
+            for entry in &ring {
+                self.write_entry(entry);
+            }
+            self.ring = Some(VecDeque::new());
+        }
+        self.flush();
+    }
+
+    pub fn flush(&mut self) {
+        if self.writer.flush().is_err() {
+            eprintln!("Failed to flush trace");
+        }
+    }
+
+    fn write_entry(&mut self, entry: &TraceEntry) {
+        let result = match self.options.format {
+            TraceFormat::Text => writeln!(
+                self.writer,
+                "{}",
+                format_entry(entry, self.platform, self.symbols.as_deref())
+            ),
+            TraceFormat::Binary => self.writer.write_all(&encode_entry(entry)),
+        };
+        if result.is_err() {
+            eprintln!("Failed to write trace entry");
+        }
+    }
+}
+
+// With symbols the address is followed by its label, e.g. "2A4 draw+4:"
+pub fn format_entry(entry: &TraceEntry, platform: Platform, symbols: Option<&Symbols>) -> String {
+    let changed: Vec<String> = entry
+        .changed
+        .iter()
+        .map(|(register, value)| format!("V{:X}={:02X}", register, value))
+        .collect();
+    let location = match symbols {
+        Some(symbols) => format!(
+            "{:03X} {:<16}",
+            entry.pc,
+            symbols.name(entry.pc).unwrap_or_default()
+        ),
+        None => format!("{:03X}", entry.pc),
+    };
+    format!(
+        "{:6} {}: {:04X}  {:<16} I={:03X} DT={:02X} ST={:02X} {}",
+        entry.frame,
+        location,
+        entry.opcode,
+        disassemble(entry.opcode, platform, symbols),
+        entry.i_register,
+        entry.delay_timer,
+        entry.sound_timer,
+        changed.join(" ")
+    )
+    .trim_end()
+    .to_string()
+}
+
+// frame u32, pc u16, opcode u16, low 16 bits of I, DT u8, ST u8, changed-register mask
+// u16, then one byte per changed register in ascending order; big-endian
+fn encode_entry(entry: &TraceEntry) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(14 + entry.changed.len());
+    bytes.extend_from_slice(&entry.frame.to_be_bytes());
+    bytes.extend_from_slice(&entry.pc.to_be_bytes());
+    bytes.extend_from_slice(&entry.opcode.to_be_bytes());
+    bytes.extend_from_slice(&(entry.i_register as u16).to_be_bytes());
+    bytes.push(entry.delay_timer);
+    bytes.push(entry.sound_timer);
+    let mask = entry
+        .changed
+        .iter()
+        .fold(0u16, |mask, (register, _)| mask | (1 << register));
+    bytes.extend_from_slice(&mask.to_be_bytes());
+    bytes.extend(entry.changed.iter().map(|(_, value)| value));
+    bytes
+}